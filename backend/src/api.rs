@@ -0,0 +1,172 @@
+use crate::config::AdminConfig;
+use crate::participant::ParticipantId;
+use crate::room::{ProducerSource, Room, RoomId};
+use crate::rooms_registry::RoomsRegistry;
+use actix_web::http::header;
+use actix_web::web::{Data, Json, Path};
+use actix_web::{HttpRequest, HttpResponse};
+use mediasoup::consumer::ConsumerScore;
+use mediasoup::prelude::*;
+use mediasoup::producer::ProducerScore;
+use serde::Serialize;
+use std::time::UNIX_EPOCH;
+
+/// Producer as seen from the HTTP API
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProducerInfo {
+    id: ProducerId,
+    kind: MediaKind,
+    source: ProducerSource,
+    paused: bool,
+    /// Latest score of each stream of the producer
+    score: Vec<ProducerScore>,
+}
+
+/// Latest score of the participant's consumer as seen from the HTTP API
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConsumerScoreInfo {
+    id: ConsumerId,
+    score: ConsumerScore,
+}
+
+/// Participant of the room as seen from the HTTP API
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ParticipantInfo {
+    id: ParticipantId,
+    name: String,
+    producers: Vec<ProducerInfo>,
+    consumer_scores: Vec<ConsumerScoreInfo>,
+}
+
+/// Room as seen from the HTTP API
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RoomInfo {
+    id: RoomId,
+    /// Creation time in milliseconds since UNIX epoch
+    created_at: u64,
+    participants: Vec<ParticipantInfo>,
+}
+
+impl RoomInfo {
+    fn new(room: &Room) -> Self {
+        let mut participants = room
+            .get_participants()
+            .into_iter()
+            .map(|(id, name)| ParticipantInfo {
+                id,
+                name,
+                producers: Vec::new(),
+                consumer_scores: room
+                    .get_scores(&id)
+                    .map(|scores| {
+                        scores
+                            .consumers
+                            .into_iter()
+                            .map(|(id, score)| ConsumerScoreInfo { id, score })
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+            })
+            .collect::<Vec<_>>();
+
+        for (participant_id, _name, producer) in room.get_all_producers() {
+            let participant = participants
+                .iter_mut()
+                .find(|participant| participant.id == participant_id);
+            if let Some(participant) = participant {
+                participant.producers.push(ProducerInfo {
+                    id: producer.id(),
+                    kind: producer.kind(),
+                    source: ProducerSource::of(&producer),
+                    paused: producer.paused(),
+                    score: room
+                        .get_scores(&participant_id)
+                        .and_then(|mut scores| scores.producers.remove(&producer.id()))
+                        .unwrap_or_default(),
+                });
+            }
+        }
+
+        Self {
+            id: room.id(),
+            created_at: room
+                .created_at()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_millis() as u64)
+                .unwrap_or_default(),
+            participants,
+        }
+    }
+}
+
+/// `GET /rooms`: list all active rooms with their participants
+pub async fn list_rooms(rooms_registry: Data<RoomsRegistry>) -> Json<Vec<RoomInfo>> {
+    let mut rooms = rooms_registry
+        .get_rooms()
+        .await
+        .iter()
+        .map(RoomInfo::new)
+        .collect::<Vec<_>>();
+    rooms.sort_by_key(|room| room.created_at);
+
+    Json(rooms)
+}
+
+/// `GET /rooms/{room_id}`: get single active room with its participants
+pub async fn get_room(room_id: Path<RoomId>, rooms_registry: Data<RoomsRegistry>) -> HttpResponse {
+    match rooms_registry.get_room(&room_id).await {
+        Some(room) => HttpResponse::Ok().json(RoomInfo::new(&room)),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+/// Whether the request carries the configured admin token as `Authorization: Bearer <token>`
+fn is_admin(request: &HttpRequest, admin: &AdminConfig) -> bool {
+    let Some(token) = admin.token() else {
+        return false;
+    };
+
+    request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|value| constant_time_eq(value.as_bytes(), token.as_bytes()))
+}
+
+/// Compare secrets without returning early on the first mismatch, so that response time doesn't
+/// tell how much of the secret was guessed right (only its length)
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// `GET /rooms/{room_id}/participants/{participant_id}/stats`: get stats of the participant's
+/// transports, producers and consumers, only available with the admin token
+pub async fn get_participant_stats(
+    request: HttpRequest,
+    path: Path<(RoomId, ParticipantId)>,
+    rooms_registry: Data<RoomsRegistry>,
+    admin: Data<AdminConfig>,
+) -> HttpResponse {
+    if !is_admin(&request, &admin) {
+        return HttpResponse::Unauthorized().finish();
+    }
+
+    let (room_id, participant_id) = path.into_inner();
+    let Some(room) = rooms_registry.get_room(&room_id).await else {
+        return HttpResponse::NotFound().finish();
+    };
+
+    match room.get_participant_stats(&participant_id).await {
+        Ok(Some(stats)) => HttpResponse::Ok().json(stats),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(error) => {
+            eprintln!("Failed to get stats of participant {participant_id}: {error}");
+            HttpResponse::InternalServerError().body(error)
+        }
+    }
+}
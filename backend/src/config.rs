@@ -0,0 +1,478 @@
+use crate::recording::backend::RecordingBackendKind;
+use crate::room::{Codec, Feedback};
+use crate::util::{get_env, get_env_or};
+use mediasoup::prelude::*;
+use mediasoup::worker::{WorkerLogLevel, WorkerLogTag};
+use serde::Deserialize;
+use std::net::{IpAddr, Ipv4Addr};
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Config file that is read when `CONFIG_FILE` is not set, it is fine for it to be missing
+const DEFAULT_CONFIG_FILE: &str = "config.toml";
+
+/// Server configuration, loaded from a TOML file and then overridden by environment variables
+/// (`.env` and `.env.local` are loaded into the environment before that)
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub webrtc: WebRtcConfig,
+    pub worker: WorkerConfig,
+    pub router: RouterConfig,
+    pub recording: RecordingConfig,
+    pub admin: AdminConfig,
+}
+
+/// HTTP/WebSocket server settings
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Address the HTTP server binds to (`LISTEN_IP`)
+    pub listen_ip: IpAddr,
+    /// Port the HTTP server binds to (`PORT`)
+    pub port: u16,
+    /// Number of HTTP worker threads (`HTTP_WORKERS`)
+    pub http_workers: usize,
+    /// Seconds for which a participant whose WebSocket connection was lost is kept in the room,
+    /// waiting for the client to reconnect with its session token, `0` disables resumption
+    /// (`SESSION_GRACE_PERIOD`)
+    pub session_grace_period: u64,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            listen_ip: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            port: 3010,
+            // 2 threads is plenty for this example
+            http_workers: 2,
+            session_grace_period: 30,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// How long participants wait to be resumed, `None` if resumption is disabled
+    pub fn session_grace_period(&self) -> Option<Duration> {
+        (self.session_grace_period > 0).then(|| Duration::from_secs(self.session_grace_period))
+    }
+}
+
+/// Settings of the WebRTC transports created for participants
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebRtcConfig {
+    /// Address mediasoup listens on for RTP (`RTC_LISTEN_IP`)
+    pub listen_ip: IpAddr,
+    /// Address advertised to clients in ICE candidates, required when `listen_ip` is
+    /// `0.0.0.0` or the server is behind NAT (`ANNOUNCED_IP`)
+    pub announced_ip: Option<IpAddr>,
+}
+
+impl Default for WebRtcConfig {
+    fn default() -> Self {
+        Self {
+            listen_ip: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            announced_ip: Some(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+        }
+    }
+}
+
+impl WebRtcConfig {
+    /// Listen IP to be used for `WebRtcTransportOptions`
+    pub fn listen_ip(&self) -> ListenIp {
+        ListenIp {
+            ip: self.listen_ip,
+            announced_ip: self.announced_ip,
+        }
+    }
+}
+
+/// Settings of the mediasoup workers
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WorkerConfig {
+    /// Number of workers in the pool, `0` means one worker per CPU core (`NUM_WORKERS`)
+    pub num_workers: usize,
+    /// Lowest RTC port used by workers (`RTC_MIN_PORT`)
+    pub rtc_min_port: u16,
+    /// Highest RTC port used by workers (`RTC_MAX_PORT`)
+    pub rtc_max_port: u16,
+    /// Worker log level (`WORKER_LOG_LEVEL`), one of `debug`, `warn`, `error` or `none`
+    pub log_level: LogLevel,
+    /// Worker log tags (`WORKER_LOG_TAGS`, comma separated)
+    pub log_tags: Vec<LogTag>,
+}
+
+impl Default for WorkerConfig {
+    fn default() -> Self {
+        Self {
+            num_workers: 0,
+            rtc_min_port: 50000,
+            rtc_max_port: 51000,
+            log_level: LogLevel(WorkerLogLevel::Debug),
+            log_tags: [
+                WorkerLogTag::Info,
+                WorkerLogTag::Ice,
+                WorkerLogTag::Dtls,
+                WorkerLogTag::Rtp,
+                WorkerLogTag::Srtp,
+                WorkerLogTag::Rtcp,
+                WorkerLogTag::Rtx,
+                WorkerLogTag::Bwe,
+                WorkerLogTag::Score,
+                WorkerLogTag::Simulcast,
+                WorkerLogTag::Svc,
+                WorkerLogTag::Sctp,
+                WorkerLogTag::Message,
+            ]
+            .into_iter()
+            .map(LogTag)
+            .collect(),
+        }
+    }
+}
+
+impl WorkerConfig {
+    /// Number of workers to spawn, with `0` resolved to the number of CPU cores
+    pub fn num_workers(&self) -> usize {
+        match self.num_workers {
+            0 => std::thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1),
+            n => n,
+        }
+    }
+
+    /// Range of ports available to WebRTC transports
+    pub fn rtc_ports_range(&self) -> RangeInclusive<u16> {
+        self.rtc_min_port..=self.rtc_max_port
+    }
+
+    /// Settings used to spawn new mediasoup workers
+    pub fn worker_settings(&self) -> WorkerSettings {
+        let mut settings = WorkerSettings::default();
+        settings.rtc_ports_range = self.rtc_ports_range();
+        settings.log_level = self.log_level.0;
+        settings.log_tags = self.log_tags.iter().map(|tag| tag.0).collect();
+
+        settings
+    }
+}
+
+/// Settings of the routers created for rooms
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RouterConfig {
+    /// Codecs offered to clients in order of preference (`ROUTER_CODECS`, comma separated), one
+    /// of `opus`, `vp8`, `vp9`, `vp9-profile2`, `h264`, `h264-main` or `h264-high`
+    pub codecs: Vec<Codec>,
+    /// RTCP feedback enabled for the codecs (`ROUTER_RTCP_FEEDBACK`, comma separated), one of
+    /// `nack`, `pli`, `fir`, `remb` or `transport-cc`
+    pub rtcp_feedback: Vec<Feedback>,
+}
+
+impl Default for RouterConfig {
+    fn default() -> Self {
+        Self {
+            // Clients pick the first codec they support, so H264 stays preferred and VP8/VP9
+            // are there for clients without H264
+            codecs: vec![Codec::Opus, Codec::H264, Codec::Vp8, Codec::Vp9],
+            rtcp_feedback: vec![
+                Feedback::Nack,
+                Feedback::Pli,
+                Feedback::Fir,
+                Feedback::Remb,
+                Feedback::TransportCc,
+            ],
+        }
+    }
+}
+
+/// Settings of the recording feature
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RecordingConfig {
+    /// Directory where recordings are stored (`RECORDING_ROOT_DIR`)
+    pub root_dir: PathBuf,
+    /// Directory for temporary SDP files passed to the recording process (`RECORDING_SDP_DIR`)
+    pub sdp_dir: PathBuf,
+    /// Lowest port used to send RTP to the recording process (`RECORDING_PORT_MIN`)
+    pub port_min: u16,
+    /// Highest port used to send RTP to the recording process (`RECORDING_PORT_MAX`)
+    pub port_max: u16,
+    /// Program recording participants, `ffmpeg` or `gstreamer` (`RECORDING_BACKEND`), room
+    /// recordings always use `ffmpeg`
+    pub backend: RecordingBackendKind,
+}
+
+impl Default for RecordingConfig {
+    fn default() -> Self {
+        Self {
+            root_dir: PathBuf::from("./recordings"),
+            sdp_dir: PathBuf::from("./profiles"),
+            port_min: 12000,
+            port_max: 13000,
+            backend: RecordingBackendKind::default(),
+        }
+    }
+}
+
+impl RecordingConfig {
+    /// Range of ports available to recordings
+    pub fn port_range(&self) -> RangeInclusive<u16> {
+        self.port_min..=self.port_max
+    }
+}
+
+/// Settings of the administrative HTTP API, which is disabled unless a token is configured
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdminConfig {
+    /// Secret that requests to the administrative API must present as a bearer token
+    /// (`ADMIN_TOKEN`)
+    pub token: Option<String>,
+}
+
+impl AdminConfig {
+    /// Configured token, `None` if the administrative API is disabled
+    pub fn token(&self) -> Option<&str> {
+        self.token.as_deref().filter(|token| !token.is_empty())
+    }
+}
+
+/// Wrapper around `WorkerLogLevel` so that it can be read from config file and environment
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(try_from = "String")]
+pub struct LogLevel(pub WorkerLogLevel);
+
+impl FromStr for LogLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let level = match s.trim().to_ascii_lowercase().as_str() {
+            "debug" => WorkerLogLevel::Debug,
+            "warn" => WorkerLogLevel::Warn,
+            "error" => WorkerLogLevel::Error,
+            "none" => WorkerLogLevel::None,
+            _ => return Err(format!("Unknown worker log level: {s}")),
+        };
+
+        Ok(Self(level))
+    }
+}
+
+impl TryFrom<String> for LogLevel {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+/// Wrapper around `WorkerLogTag` so that it can be read from config file and environment
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(try_from = "String")]
+pub struct LogTag(pub WorkerLogTag);
+
+impl FromStr for LogTag {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tag = match s.trim().to_ascii_lowercase().as_str() {
+            "info" => WorkerLogTag::Info,
+            "ice" => WorkerLogTag::Ice,
+            "dtls" => WorkerLogTag::Dtls,
+            "rtp" => WorkerLogTag::Rtp,
+            "srtp" => WorkerLogTag::Srtp,
+            "rtcp" => WorkerLogTag::Rtcp,
+            "rtx" => WorkerLogTag::Rtx,
+            "bwe" => WorkerLogTag::Bwe,
+            "score" => WorkerLogTag::Score,
+            "simulcast" => WorkerLogTag::Simulcast,
+            "svc" => WorkerLogTag::Svc,
+            "sctp" => WorkerLogTag::Sctp,
+            "message" => WorkerLogTag::Message,
+            _ => return Err(format!("Unknown worker log tag: {s}")),
+        };
+
+        Ok(Self(tag))
+    }
+}
+
+impl TryFrom<String> for LogTag {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl Config {
+    /// Load configuration from the file specified by `CONFIG_FILE` (or `config.toml` if it
+    /// exists), apply environment variable overrides and validate the result
+    pub fn load() -> Result<Self, String> {
+        let path = get_env_or::<String>("CONFIG_FILE", DEFAULT_CONFIG_FILE.to_owned());
+
+        let mut config = match std::fs::read_to_string(&path) {
+            Ok(text) => toml::from_str::<Config>(&text)
+                .map_err(|error| format!("Failed to parse {path}: {error}"))?,
+            Err(error)
+                if error.kind() == std::io::ErrorKind::NotFound
+                    && std::env::var("CONFIG_FILE").is_err() =>
+            {
+                Config::default()
+            }
+            Err(error) => return Err(format!("Failed to read {path}: {error}")),
+        };
+
+        config.apply_env_overrides()?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    fn apply_env_overrides(&mut self) -> Result<(), String> {
+        if let Some(v) = get_env("LISTEN_IP")? {
+            self.server.listen_ip = v;
+        }
+        if let Some(v) = get_env("PORT")? {
+            self.server.port = v;
+        }
+        if let Some(v) = get_env("HTTP_WORKERS")? {
+            self.server.http_workers = v;
+        }
+        if let Some(v) = get_env("SESSION_GRACE_PERIOD")? {
+            self.server.session_grace_period = v;
+        }
+        if let Some(v) = get_env("RTC_LISTEN_IP")? {
+            self.webrtc.listen_ip = v;
+        }
+        if let Some(v) = get_env::<String>("ANNOUNCED_IP")? {
+            // Allow clearing the announced IP with an empty value
+            self.webrtc.announced_ip = match v.trim() {
+                "" => None,
+                v => Some(
+                    v.parse()
+                        .map_err(|error| format!("ANNOUNCED_IP is invalid: {error}"))?,
+                ),
+            };
+        }
+        if let Some(v) = get_env("NUM_WORKERS")? {
+            self.worker.num_workers = v;
+        }
+        if let Some(v) = get_env("RTC_MIN_PORT")? {
+            self.worker.rtc_min_port = v;
+        }
+        if let Some(v) = get_env("RTC_MAX_PORT")? {
+            self.worker.rtc_max_port = v;
+        }
+        if let Some(v) = get_env("WORKER_LOG_LEVEL")? {
+            self.worker.log_level = v;
+        }
+        if let Some(v) = get_env::<String>("WORKER_LOG_TAGS")? {
+            self.worker.log_tags = v
+                .split(',')
+                .filter(|tag| !tag.trim().is_empty())
+                .map(LogTag::from_str)
+                .collect::<Result<_, _>>()
+                .map_err(|error| format!("WORKER_LOG_TAGS is invalid: {error}"))?;
+        }
+        if let Some(v) = get_env::<String>("ROUTER_CODECS")? {
+            self.router.codecs = v
+                .split(',')
+                .filter(|codec| !codec.trim().is_empty())
+                .map(Codec::from_str)
+                .collect::<Result<_, _>>()
+                .map_err(|error| format!("ROUTER_CODECS is invalid: {error}"))?;
+        }
+        if let Some(v) = get_env::<String>("ROUTER_RTCP_FEEDBACK")? {
+            self.router.rtcp_feedback = v
+                .split(',')
+                .filter(|feedback| !feedback.trim().is_empty())
+                .map(Feedback::from_str)
+                .collect::<Result<_, _>>()
+                .map_err(|error| format!("ROUTER_RTCP_FEEDBACK is invalid: {error}"))?;
+        }
+        if let Some(v) = get_env("RECORDING_ROOT_DIR")? {
+            self.recording.root_dir = v;
+        }
+        if let Some(v) = get_env("RECORDING_SDP_DIR")? {
+            self.recording.sdp_dir = v;
+        }
+        if let Some(v) = get_env("RECORDING_PORT_MIN")? {
+            self.recording.port_min = v;
+        }
+        if let Some(v) = get_env("RECORDING_PORT_MAX")? {
+            self.recording.port_max = v;
+        }
+        if let Some(v) = get_env("RECORDING_BACKEND")? {
+            self.recording.backend = v;
+        }
+        if let Some(v) = get_env("ADMIN_TOKEN")? {
+            self.admin.token = Some(v);
+        }
+
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.server.http_workers == 0 {
+            return Err("server.http_workers must be at least 1".to_owned());
+        }
+
+        if self.webrtc.listen_ip.is_unspecified() && self.webrtc.announced_ip.is_none() {
+            return Err(format!(
+                "webrtc.announced_ip is required when webrtc.listen_ip is {}",
+                self.webrtc.listen_ip
+            ));
+        }
+
+        if self.worker.rtc_min_port > self.worker.rtc_max_port {
+            return Err(format!(
+                "worker.rtc_min_port ({}) must not be greater than worker.rtc_max_port ({})",
+                self.worker.rtc_min_port, self.worker.rtc_max_port
+            ));
+        }
+
+        for kind in [MediaKind::Audio, MediaKind::Video] {
+            if !self.router.codecs.iter().any(|codec| codec.kind() == kind) {
+                return Err(format!(
+                    "router.codecs must contain at least one {kind:?} codec"
+                ));
+            }
+        }
+        for (i, codec) in self.router.codecs.iter().enumerate() {
+            if self.router.codecs[..i].contains(codec) {
+                return Err(format!("router.codecs contains {codec:?} more than once"));
+            }
+        }
+
+        if self.recording.port_min == 0 {
+            return Err("recording.port_min must not be 0".to_owned());
+        }
+        // Each recording uses 4 consecutive ports (RTP and RTCP for audio and video)
+        if self.recording.port_min > self.recording.port_max
+            || self.recording.port_max - self.recording.port_min < 3
+        {
+            return Err(format!(
+                "recording port range {}..={} must contain at least 4 ports",
+                self.recording.port_min, self.recording.port_max
+            ));
+        }
+
+        let rtc_ports = self.worker.rtc_ports_range();
+        let recording_ports = self.recording.port_range();
+        if rtc_ports.start() <= recording_ports.end() && recording_ports.start() <= rtc_ports.end()
+        {
+            return Err(format!(
+                "recording port range {recording_ports:?} overlaps with RTC port range {rtc_ports:?}"
+            ));
+        }
+
+        Ok(())
+    }
+}
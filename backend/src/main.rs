@@ -1,15 +1,19 @@
+use actix_cors::Cors;
 use actix_web::web::{Data, Payload, Query};
 use actix_web::{web, App, Error, HttpRequest, HttpResponse, HttpServer};
 use actix_web_actors::ws;
 use mediasoup::prelude::*;
 use serde::Deserialize;
-use std::net::IpAddr;
+use std::sync::Arc;
 
+mod api;
+mod config;
 mod participant;
 mod recording;
 mod room;
 mod rooms_registry;
 mod util;
+mod worker_pool;
 
 // use participant::ParticipantConnection;
 // use room::RoomId;
@@ -19,6 +23,11 @@ mod util;
 #[serde(rename_all = "camelCase")]
 struct QueryParameters {
     room_id: Option<room::RoomId>,
+    /// Token from `Init` message of the previous connection when the client reconnects
+    session_token: Option<participant::session::SessionToken>,
+    /// Create both producer and consumer transports right away and send them with `Init`
+    #[serde(default)]
+    bundle_transports: bool,
 }
 
 /// Function that receives HTTP request on WebSocket route and upgrades it to WebSocket connection.
@@ -27,18 +36,21 @@ struct QueryParameters {
 async fn ws_index(
     query_parameters: Query<QueryParameters>,
     request: HttpRequest,
-    worker_manager: Data<WorkerManager>,
     rooms_registry: Data<rooms_registry::RoomsRegistry>,
+    sessions: Data<participant::session::SessionStore>,
     stream: Payload,
 ) -> Result<HttpResponse, Error> {
+    // Reattach to the same participant if the session hasn't expired yet
+    if let Some(session_token) = query_parameters.session_token {
+        if let Some(connection) = sessions.resume(&session_token, query_parameters.room_id) {
+            return ws::start(connection, &request, stream);
+        }
+    }
+
     // WebSocket のクエリにルーム ID が含まれている
     let room = match query_parameters.room_id {
-        Some(room_id) => {
-            rooms_registry
-                .get_or_create_room(&worker_manager, room_id)
-                .await
-        }
-        None => rooms_registry.create_room(&worker_manager).await,
+        Some(room_id) => rooms_registry.get_or_create_room(room_id).await,
+        None => rooms_registry.create_room().await,
     };
 
     let room = match room {
@@ -50,7 +62,13 @@ async fn ws_index(
         }
     };
 
-    match participant::ParticipantConnection::new(room).await {
+    match participant::ParticipantConnection::new(
+        room,
+        sessions.get_ref().clone(),
+        query_parameters.bundle_transports,
+    )
+    .await
+    {
         Ok(echo_server) => ws::start(echo_server, &request, stream),
         Err(error) => {
             eprintln!("{error}");
@@ -67,25 +85,60 @@ async fn main() -> std::io::Result<()> {
 
     env_logger::init();
 
-    let addr = util::get_env::<IpAddr>("LISTEN_IP").unwrap();
-    let port = util::get_env::<u16>("PORT").unwrap();
+    let config = match config::Config::load() {
+        Ok(config) => Arc::new(config),
+        Err(error) => {
+            eprintln!("Invalid configuration: {error}");
+            std::process::exit(1);
+        }
+    };
+    let addr = config.server.listen_ip;
+    let port = config.server.port;
+    let http_workers = config.server.http_workers;
+    let admin = Data::new(config.admin.clone());
 
-    // We will reuse the same worker manager across all connections, this is more than enough for
-    // this use case
-    let worker_manager = Data::new(WorkerManager::new());
+    // Workers are shared by all the rooms, each room is placed on the least loaded one
+    let worker_pool =
+        match worker_pool::WorkerPool::new(WorkerManager::new(), config.worker.clone()).await {
+            Ok(worker_pool) => worker_pool,
+            Err(error) => {
+                eprintln!("{error}");
+                std::process::exit(1);
+            }
+        };
     // Rooms registry will hold all the active rooms
-    let rooms_registry = Data::new(rooms_registry::RoomsRegistry::default());
+    let rooms_registry = Data::new(rooms_registry::RoomsRegistry::new(worker_pool, config));
+    // Sessions of participants that lost connection and may reconnect shortly
+    let sessions = Data::new(participant::session::SessionStore::new());
 
     log::info!("Listening on {}:{}", addr, port);
 
     HttpServer::new(move || {
         App::new()
-            .app_data(worker_manager.clone())
             .app_data(rooms_registry.clone())
-            .route("/ws", web::get().to(ws_index))
+            .app_data(sessions.clone())
+            .app_data(admin.clone())
+            // Administrative API is only mounted when a token is configured and is not exposed
+            // to other origins, it is registered first so that the scope below doesn't take over
+            .configure(|cfg| {
+                if admin.token().is_some() {
+                    cfg.route(
+                        "/rooms/{room_id}/participants/{participant_id}/stats",
+                        web::get().to(api::get_participant_stats),
+                    );
+                }
+            })
+            .service(
+                web::scope("")
+                    // Read-only room information is fetched by the frontend served from another
+                    // origin
+                    .wrap(Cors::default().allow_any_origin().allowed_methods(["GET"]))
+                    .route("/ws", web::get().to(ws_index))
+                    .route("/rooms", web::get().to(api::list_rooms))
+                    .route("/rooms/{room_id}", web::get().to(api::get_room)),
+            )
     })
-    // 2 threads is plenty for this example, default is to have as many threads as CPU cores
-    .workers(2)
+    .workers(http_workers)
     .bind(format!("{}:{}", addr, port))?
     .run()
     .await
@@ -1,17 +1,28 @@
-use crate::room::Room;
-use crate::util::get_env;
+use crate::recording::{RecordingEvent, RecordingMode};
+use crate::room::{ParticipantScores, ProducerSource, Room};
 use actix::prelude::*;
 use actix_web_actors::ws;
 use event_listener_primitives::HandlerId;
+use mediasoup::data_structures::{AppData, DtlsState, IceState};
 use mediasoup::prelude::*;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
-use std::net::{IpAddr, Ipv4Addr};
+use std::time::Duration;
 use uuid::Uuid;
 
+/// Scores change often on bad connections, client is notified about them at most this often
+const SCORE_INTERVAL: Duration = Duration::from_secs(2);
+/// Each producer transport takes up a port, so a single client can't create too many of them
+const MAX_PRODUCER_TRANSPORTS: usize = 4;
+
 pub mod messages;
-use messages::{ClientMessage, InternalMessage, ServerMessage, TransportOptions};
+pub mod session;
+use messages::{
+    ClientMessage, ClientRequest, ErrorCode, InternalMessage, RequestId, ServerMessage,
+    TransportDirection, TransportOptions,
+};
+use session::{SessionAddress, SessionStore, SessionToken};
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd, Deserialize, Serialize)]
 pub struct ParticipantId(Uuid);
@@ -28,10 +39,59 @@ impl ParticipantId {
     }
 }
 
-/// Consumer/producer transports pair for the client
+/// Consumer/producer transports of the client, each of them is created only when the client
+/// needs it. There may be several producer transports (for instance screen share may be sent
+/// separately from the camera), but only one consumer transport.
+#[derive(Clone, Default)]
 struct Transports {
-    consumer: WebRtcTransport,
-    producer: WebRtcTransport,
+    consumer: Option<WebRtcTransport>,
+    producers: Vec<WebRtcTransport>,
+}
+
+impl Transports {
+    /// Producer transport with given ID, or the first one if ID is not specified
+    fn producer(&self, transport_id: Option<TransportId>) -> Option<&WebRtcTransport> {
+        match transport_id {
+            Some(transport_id) => self
+                .producers
+                .iter()
+                .find(|transport| transport.id() == transport_id),
+            None => self.producers.first(),
+        }
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &WebRtcTransport> {
+        self.producers.iter().chain(self.consumer.iter())
+    }
+}
+
+/// Create a new WebRTC transport on the router of the room
+async fn create_transport(
+    room: &Room,
+    direction: TransportDirection,
+) -> Result<WebRtcTransport, String> {
+    let mut transport_options =
+        WebRtcTransportOptions::new(TransportListenIps::new(room.config().webrtc.listen_ip()));
+    transport_options.enable_tcp = true;
+    transport_options.prefer_udp = true;
+    // Data channels are sent over SCTP
+    transport_options.enable_sctp = true;
+
+    room.router()
+        .create_webrtc_transport(transport_options)
+        .await
+        .map_err(|error| format!("Failed to create {direction:?} transport: {error}"))
+}
+
+/// Options client needs to create its side of the transport
+fn transport_options(transport: &WebRtcTransport) -> TransportOptions {
+    TransportOptions {
+        id: transport.id(),
+        dtls_parameters: transport.dtls_parameters(),
+        ice_candidates: transport.ice_candidates().clone(),
+        ice_parameters: transport.ice_parameters().clone(),
+        sctp_parameters: transport.sctp_parameters(),
+    }
 }
 
 /// Actor that will represent WebSocket connection from the client, it will handle inbound and
@@ -47,6 +107,10 @@ pub struct ParticipantConnection {
     consumers: HashMap<ConsumerId, Consumer>,
     /// Producers associated with this client, preventing them from being destroyed
     producers: Vec<Producer>,
+    /// Data consumers associated with this client, preventing them from being destroyed
+    data_consumers: HashMap<DataConsumerId, DataConsumer>,
+    /// Data producers associated with this client, preventing them from being destroyed
+    data_producers: Vec<DataProducer>,
     /// Consumer and producer transports associated with this client
     transports: Transports,
     /// Room to which the client belongs
@@ -54,38 +118,49 @@ pub struct ParticipantConnection {
     /// Event handlers that were attached and need to be removed when participant connection is
     /// destroyed
     attached_handlers: Vec<HandlerId>,
+    /// Scores changed since the client was last notified
+    pending_scores: ParticipantScores,
+    /// Token with which the client can resume this session after reconnecting
+    session_token: SessionToken,
+    /// Address of the actor currently serving the session, shared with the event handlers of
+    /// consumers and producers
+    address: SessionAddress,
+    /// Store where the session is kept when WebSocket connection is lost
+    sessions: SessionStore,
+    /// Whether this connection continues a suspended session
+    resumed: bool,
+    /// Set when the connection is closed on purpose, session is not kept for resumption then
+    closing: bool,
+    /// Set when the session was handed over to the session store, participant must stay in the
+    /// room then
+    suspended: bool,
 }
 
 impl Drop for ParticipantConnection {
     fn drop(&mut self) {
-        self.room.remove_participant(&self.id);
+        if !self.suspended {
+            self.room.remove_participant(&self.id);
+        }
     }
 }
 
 impl ParticipantConnection {
-    /// Create a new instance representing WebSocket connection
-    pub async fn new(room: Room) -> Result<Self, String> {
-        // We know that for videoroom example we'll need 2 transports, so we can create both
-        // right away. This may not be the case for real-world applications or you may create
-        // this at a different time and/or in different order.
-        let mut transport_options =
-            WebRtcTransportOptions::new(TransportListenIps::new(ListenIp {
-                ip: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
-                announced_ip: Some(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))),
-            }));
-        transport_options.enable_tcp = true;
-        transport_options.prefer_udp = true;
-        let producer_transport = room
-            .router()
-            .create_webrtc_transport(transport_options.clone())
-            .await
-            .map_err(|error| format!("Failed to create producer transport: {error}"))?;
-
-        let consumer_transport = room
-            .router()
-            .create_webrtc_transport(transport_options)
-            .await
-            .map_err(|error| format!("Failed to create consumer transport: {error}"))?;
+    /// Create a new instance representing WebSocket connection, with `bundle_transports` both
+    /// consumer and producer transports are created right away and sent with `Init`, otherwise
+    /// the client creates only those it needs with `CreateTransport`
+    pub async fn new(
+        room: Room,
+        sessions: SessionStore,
+        bundle_transports: bool,
+    ) -> Result<Self, String> {
+        let mut transports = Transports::default();
+        if bundle_transports {
+            transports
+                .producers
+                .push(create_transport(&room, TransportDirection::Producer).await?);
+            transports.consumer =
+                Some(create_transport(&room, TransportDirection::Consumer).await?);
+        }
 
         Ok(Self {
             id: ParticipantId::new(),
@@ -93,14 +168,183 @@ impl ParticipantConnection {
             client_rtp_capabilities: None,
             consumers: HashMap::new(),
             producers: vec![],
-            transports: Transports {
-                consumer: consumer_transport,
-                producer: producer_transport,
-            },
+            data_consumers: HashMap::new(),
+            data_producers: vec![],
+            transports,
             room,
             attached_handlers: Vec::new(),
+            pending_scores: ParticipantScores::default(),
+            session_token: SessionToken::new(),
+            address: SessionAddress::default(),
+            sessions,
+            resumed: false,
+            closing: false,
+            suspended: false,
         })
     }
+
+    /// Hand over participant's state to a new instance that can be resumed by another WebSocket
+    /// connection, this instance will no longer remove participant from the room when dropped
+    fn suspend(&mut self) -> Self {
+        self.suspended = true;
+
+        Self {
+            id: self.id,
+            name: self.name.clone(),
+            client_rtp_capabilities: self.client_rtp_capabilities.take(),
+            consumers: std::mem::take(&mut self.consumers),
+            producers: std::mem::take(&mut self.producers),
+            data_consumers: std::mem::take(&mut self.data_consumers),
+            data_producers: std::mem::take(&mut self.data_producers),
+            transports: self.transports.clone(),
+            room: self.room.clone(),
+            attached_handlers: Vec::new(),
+            pending_scores: ParticipantScores::default(),
+            session_token: self.session_token,
+            address: self.address.clone(),
+            sessions: self.sessions.clone(),
+            resumed: true,
+            closing: false,
+            suspended: false,
+        }
+    }
+
+    /// Let the client know when connection of the transport is lost so that it can restart ICE
+    fn attach_transport_handlers(&mut self, transport: &WebRtcTransport, address: &Addr<Self>) {
+        let transport_id = transport.id();
+
+        self.attached_handlers.push(transport.on_ice_state_change({
+            let address = address.clone();
+
+            move |ice_state| {
+                // Terminal states are reported too, transport can't recover from them
+                let reason = match ice_state {
+                    IceState::Disconnected => "ICE disconnected",
+                    IceState::Closed => "ICE closed",
+                    _ => return,
+                };
+                address.do_send(ServerMessage::TransportDisconnected {
+                    transport_id,
+                    reason: reason.to_owned(),
+                });
+            }
+        }));
+
+        self.attached_handlers.push(transport.on_dtls_state_change({
+            let address = address.clone();
+
+            move |dtls_state| {
+                let reason = match dtls_state {
+                    DtlsState::Failed => "DTLS failed",
+                    DtlsState::Closed => "DTLS closed",
+                    _ => return,
+                };
+                address.do_send(ServerMessage::TransportDisconnected {
+                    transport_id,
+                    reason: reason.to_owned(),
+                });
+            }
+        }));
+    }
+
+    /// Respond with an error when the client uses a transport it hasn't created
+    fn transport_not_found(
+        ctx: &mut <Self as Actor>::Context,
+        action: &'static str,
+        request_id: Option<RequestId>,
+        direction: TransportDirection,
+        transport_id: Option<TransportId>,
+    ) {
+        let message = match transport_id {
+            Some(transport_id) => format!("{direction:?} transport {transport_id} doesn't exist"),
+            None => format!("{direction:?} transport should be created with CreateTransport first"),
+        };
+        ctx.address().do_send(ServerMessage::error(
+            Some(action),
+            request_id,
+            ErrorCode::TransportNotFound,
+            message,
+        ));
+    }
+
+    /// Notify client about the latest scores of the producers and consumers that have changed
+    fn send_pending_scores(&mut self, ctx: &mut <Self as Actor>::Context) {
+        let ParticipantScores {
+            producers,
+            consumers,
+        } = std::mem::take(&mut self.pending_scores);
+
+        for (producer_id, score) in producers {
+            ctx.address()
+                .do_send(ServerMessage::ProducerScore { producer_id, score });
+        }
+        for (consumer_id, score) in consumers {
+            ctx.address()
+                .do_send(ServerMessage::ConsumerScore { consumer_id, score });
+        }
+    }
+
+    /// Pause or resume own producer and respond with `ProducerPaused`/`ProducerResumed`
+    fn set_producer_paused(
+        &self,
+        ctx: &mut <Self as Actor>::Context,
+        action: &'static str,
+        request_id: Option<RequestId>,
+        producer_id: ProducerId,
+        paused: bool,
+    ) {
+        if !self
+            .producers
+            .iter()
+            .any(|producer| producer.id() == producer_id)
+        {
+            ctx.address().do_send(ServerMessage::error(
+                Some(action),
+                request_id,
+                ErrorCode::ProducerNotFound,
+                format!("Producer {producer_id} doesn't exist"),
+            ));
+            return;
+        }
+
+        let participant_id = self.id;
+        let address = ctx.address();
+        let room = self.room.clone();
+        actix::spawn(async move {
+            match room
+                .set_producer_paused(&participant_id, &producer_id, paused)
+                .await
+            {
+                Ok(()) => {
+                    println!(
+                        "[participant_id {participant_id}] Producer {producer_id} paused: {paused}"
+                    );
+                    address.do_send(if paused {
+                        ServerMessage::ProducerPaused {
+                            request_id,
+                            participant_id,
+                            producer_id,
+                        }
+                    } else {
+                        ServerMessage::ProducerResumed {
+                            request_id,
+                            participant_id,
+                            producer_id,
+                        }
+                    });
+                }
+                Err(error) => {
+                    eprintln!("[participant_id {participant_id}] {error}");
+                    address.do_send(ServerMessage::error(
+                        Some(action),
+                        request_id,
+                        ErrorCode::ProducerPauseFailed,
+                        error,
+                    ));
+                }
+            }
+        });
+    }
 }
 
 impl Actor for ParticipantConnection {
@@ -109,28 +353,37 @@ impl Actor for ParticipantConnection {
     fn started(&mut self, ctx: &mut Self::Context) {
         println!("[participant_id {}] WebSocket connection created", self.id);
 
-        // We know that both consumer and producer transports will be used, so we sent server
-        // information about both in an initialization message alongside with router
-        // capabilities to the client right after WebSocket connection is established
+        // Send information about transports that exist already (if any) in an initialization
+        // message alongside with router capabilities to the client right after WebSocket
+        // connection is established
+        let grace_period = self.room.config().server.session_grace_period();
         let server_init_message = ServerMessage::Init {
             room_id: self.room.id(),
-            consumer_transport_options: TransportOptions {
-                id: self.transports.consumer.id(),
-                dtls_parameters: self.transports.consumer.dtls_parameters(),
-                ice_candidates: self.transports.consumer.ice_candidates().clone(),
-                ice_parameters: self.transports.consumer.ice_parameters().clone(),
-            },
-            producer_transport_options: TransportOptions {
-                id: self.transports.producer.id(),
-                dtls_parameters: self.transports.producer.dtls_parameters(),
-                ice_candidates: self.transports.producer.ice_candidates().clone(),
-                ice_parameters: self.transports.producer.ice_parameters().clone(),
-            },
+            participant_id: self.id,
+            session_token: grace_period.map(|_| self.session_token),
+            resumed: self.resumed,
+            consumer_transport_options: self.transports.consumer.as_ref().map(transport_options),
+            producer_transport_options: self.transports.producers.first().map(transport_options),
             router_rtp_capabilities: self.room.router().rtp_capabilities().clone(),
         };
 
         let address = ctx.address();
         address.do_send(server_init_message);
+        self.address.set(Some(address.clone()));
+
+        let transports = self.transports.clone();
+        for transport in transports.iter() {
+            // Transports are kept in the room too so that their stats can be collected, resumed
+            // session has them there already
+            if !self.resumed {
+                self.room.add_transport(self.id, transport.clone());
+            }
+            self.attach_transport_handlers(transport, &address);
+        }
+
+        ctx.run_interval(SCORE_INTERVAL, |connection, ctx| {
+            connection.send_pending_scores(ctx);
+        });
 
         // Listen for new producers added to the room
         self.attached_handlers.push(self.room.on_producer_add({
@@ -145,6 +398,8 @@ impl Actor for ParticipantConnection {
                     participant_id: *participant_id,
                     name: name.to_string(),
                     producer_id: producer.id(),
+                    source: ProducerSource::of(producer),
+                    paused: producer.paused(),
                 });
             }
         }));
@@ -159,24 +414,233 @@ impl Actor for ParticipantConnection {
                     return;
                 }
                 address.do_send(ServerMessage::ProducerRemoved {
+                    request_id: None,
                     participant_id: *participant_id,
                     producer_id: *producer_id,
                 });
             }
         }));
 
-        // Notify client about any producers that already exist in the room
-        for (participant_id, name, producer_id) in self.room.get_all_producers() {
-            address.do_send(ServerMessage::ProducerAdded {
+        // Listen for data channels added to and removed from the room by others
+        self.attached_handlers.push(self.room.on_data_producer_add({
+            let own_participant_id = self.id;
+            let address = address.clone();
+
+            move |participant_id, name, data_producer| {
+                if &own_participant_id == participant_id {
+                    return;
+                }
+                address.do_send(ServerMessage::DataProducerAdded {
+                    participant_id: *participant_id,
+                    name: name.to_string(),
+                    data_producer_id: data_producer.id(),
+                    label: data_producer.label().clone(),
+                    protocol: data_producer.protocol().clone(),
+                });
+            }
+        }));
+
+        self.attached_handlers
+            .push(self.room.on_data_producer_remove({
+                let own_participant_id = self.id;
+                let address = address.clone();
+
+                move |participant_id, data_producer_id| {
+                    if &own_participant_id == participant_id {
+                        return;
+                    }
+                    address.do_send(ServerMessage::DataProducerRemoved {
+                        request_id: None,
+                        participant_id: *participant_id,
+                        data_producer_id: *data_producer_id,
+                    });
+                }
+            }));
+
+        // Listen for producers paused and resumed by others, own changes are sent as a response
+        // to the request instead
+        self.attached_handlers.push(self.room.on_producer_pause({
+            let own_participant_id = self.id;
+            let address = address.clone();
+
+            move |participant_id, producer_id, paused| {
+                if &own_participant_id == participant_id {
+                    return;
+                }
+                let participant_id = *participant_id;
+                let producer_id = *producer_id;
+                address.do_send(if *paused {
+                    ServerMessage::ProducerPaused {
+                        request_id: None,
+                        participant_id,
+                        producer_id,
+                    }
+                } else {
+                    ServerMessage::ProducerResumed {
+                        request_id: None,
+                        participant_id,
+                        producer_id,
+                    }
+                });
+            }
+        }));
+
+        // Listen for recordings started and stopped by anyone in the room, own successful
+        // start/stop is sent as a response to the request instead
+        self.attached_handlers.push(self.room.on_recording({
+            let own_participant_id = self.id;
+            let address = address.clone();
+
+            move |participant_id, mode, event| {
+                if &own_participant_id == participant_id
+                    && !matches!(event, RecordingEvent::Failed { .. })
+                {
+                    return;
+                }
+                address.do_send(ServerMessage::recording(
+                    None,
+                    *participant_id,
+                    *mode,
+                    event,
+                ));
+            }
+        }));
+
+        // Listen for the loudest participant (including self) so that client can highlight them
+        self.attached_handlers.push(self.room.on_active_speaker({
+            let address = address.clone();
+
+            move |participant_id, volume| {
+                address.do_send(ServerMessage::ActiveSpeaker {
+                    participant_id: *participant_id,
+                    volume: *volume,
+                });
+            }
+        }));
+
+        self.attached_handlers.push(self.room.on_silence({
+            let address = address.clone();
+
+            move || {
+                address.do_send(ServerMessage::Silence);
+            }
+        }));
+
+        // Room is unusable once its worker dies, client will need to reconnect
+        self.attached_handlers.push(self.room.on_worker_close({
+            let address = address.clone();
+
+            move || {
+                address.do_send(ServerMessage::RoomClosed {
+                    reason: "Media worker of the room has died".to_owned(),
+                });
+                address.do_send(InternalMessage::Stop);
+            }
+        }));
+
+        // Consumers closed while the session was suspended were not removed since nobody was
+        // listening, do it now
+        if self.resumed {
+            let closed_consumers = self
+                .consumers
+                .values()
+                .filter(|consumer| consumer.closed())
+                .map(|consumer| consumer.id())
+                .collect::<Vec<_>>();
+            for consumer_id in closed_consumers {
+                self.consumers.remove(&consumer_id);
+                self.room.remove_consumer(&self.id, &consumer_id);
+                address.do_send(ServerMessage::ConsumerClosed { consumer_id });
+            }
+
+            self.data_consumers
+                .retain(|&data_consumer_id, data_consumer| {
+                    let closed = data_consumer.closed();
+                    if closed {
+                        address.do_send(ServerMessage::DataConsumerClosed { data_consumer_id });
+                    }
+                    !closed
+                });
+        }
+        let consumed_producers = self
+            .consumers
+            .values()
+            .map(|consumer| consumer.producer_id())
+            .collect::<HashSet<_>>();
+
+        // Notify client about any producers that already exist in the room, resumed client only
+        // needs to know about those it doesn't consume yet and current state of the rest
+        for (participant_id, name, producer) in self.room.get_all_producers() {
+            let producer_id = producer.id();
+            if !consumed_producers.contains(&producer_id) {
+                address.do_send(ServerMessage::ProducerAdded {
+                    participant_id,
+                    name: name.to_owned(),
+                    producer_id,
+                    source: ProducerSource::of(&producer),
+                    paused: producer.paused(),
+                });
+            } else if producer.paused() {
+                address.do_send(ServerMessage::ProducerPaused {
+                    request_id: None,
+                    participant_id,
+                    producer_id,
+                });
+            } else {
+                address.do_send(ServerMessage::ProducerResumed {
+                    request_id: None,
+                    participant_id,
+                    producer_id,
+                });
+            }
+        }
+
+        // The same for data producers
+        let consumed_data_producers = self
+            .data_consumers
+            .values()
+            .map(|data_consumer| data_consumer.data_producer_id())
+            .collect::<HashSet<_>>();
+        for (participant_id, name, data_producer) in self.room.get_all_data_producers() {
+            if !consumed_data_producers.contains(&data_producer.id()) {
+                address.do_send(ServerMessage::DataProducerAdded {
+                    participant_id,
+                    name,
+                    data_producer_id: data_producer.id(),
+                    label: data_producer.label().clone(),
+                    protocol: data_producer.protocol().clone(),
+                });
+            }
+        }
+
+        // Notify client about recordings that are already in progress
+        for (participant_id, mode, recording_id) in self.room.get_recordings() {
+            address.do_send(ServerMessage::RecordingStarted {
+                request_id: None,
                 participant_id,
-                name: name.to_owned(),
-                producer_id,
+                mode,
+                recording_id,
             });
         }
     }
 
     fn stopped(&mut self, _ctx: &mut Self::Context) {
         println!("[participant_id {0}] WebSocket connection closed", self.id);
+        self.address.set(None);
+
+        // Connection was lost rather than closed, keep participant in the room for a while so
+        // that the client can resume the session
+        if let Some(grace_period) = self.room.config().server.session_grace_period() {
+            if !self.closing && !self.room.is_closed() {
+                println!(
+                    "[participant_id {}] Session suspended for {grace_period:?}",
+                    self.id
+                );
+                let connection = self.suspend();
+                self.sessions
+                    .suspend(self.session_token, connection, grace_period);
+            }
+        }
     }
 }
 
@@ -190,7 +654,7 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for ParticipantConnec
                 ctx.pong(&msg);
             }
             Ok(ws::Message::Pong(_)) => {}
-            Ok(ws::Message::Text(text)) => match serde_json::from_str::<ClientMessage>(&text) {
+            Ok(ws::Message::Text(text)) => match serde_json::from_str::<ClientRequest>(&text) {
                 Ok(message) => {
                     // Parse JSON into an enum and just send it back to the actor to be
                     // processed by another handler below, it is much more convenient to just
@@ -199,12 +663,38 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for ParticipantConnec
                 }
                 Err(error) => {
                     eprintln!("Failed to parse client message: {error}\n{text}");
+                    // Try to at least find out which request has failed, so that the client can
+                    // match the error with it
+                    let value = serde_json::from_str::<serde_json::Value>(&text).ok();
+                    let request_action = value
+                        .as_ref()
+                        .and_then(|value| value.get("action")?.as_str());
+                    let request_id = value
+                        .as_ref()
+                        .and_then(|value| value.get("requestId"))
+                        .and_then(|request_id| {
+                            serde_json::from_value::<RequestId>(request_id.clone()).ok()
+                        });
+                    ctx.address().do_send(ServerMessage::error(
+                        request_action,
+                        request_id,
+                        ErrorCode::InvalidMessage,
+                        format!("Failed to parse client message: {error}"),
+                    ));
                 }
             },
             Ok(ws::Message::Binary(bin)) => {
                 eprintln!("Unexpected binary message: {bin:?}");
+                ctx.address().do_send(ServerMessage::error(
+                    None,
+                    None,
+                    ErrorCode::InvalidMessage,
+                    "Binary messages are not supported",
+                ));
             }
             Ok(ws::Message::Close(reason)) => {
+                // Client left on purpose, there is nothing to resume
+                self.closing = true;
                 ctx.close(reason);
                 ctx.stop();
             }
@@ -213,10 +703,15 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for ParticipantConnec
     }
 }
 
-impl Handler<ClientMessage> for ParticipantConnection {
+impl Handler<ClientRequest> for ParticipantConnection {
     type Result = ();
 
-    fn handle(&mut self, message: ClientMessage, ctx: &mut Self::Context) {
+    fn handle(&mut self, request: ClientRequest, ctx: &mut Self::Context) {
+        let ClientRequest {
+            request_id,
+            message,
+        } = request;
+        let action = message.action();
         match message {
             ClientMessage::Init {
                 name,
@@ -228,10 +723,78 @@ impl Handler<ClientMessage> for ParticipantConnection {
                 self.name = name.clone();
                 self.room.set_participant_name(self.id, name);
             }
-            ClientMessage::ConnectProducerTransport { dtls_parameters } => {
+            ClientMessage::CreateTransport { direction } => {
+                let participant_id = self.id;
+                let address = ctx.address();
+                match direction {
+                    TransportDirection::Producer => {
+                        if self.transports.producers.len() >= MAX_PRODUCER_TRANSPORTS {
+                            address.do_send(ServerMessage::error(
+                                Some(action),
+                                request_id,
+                                ErrorCode::TransportCreateFailed,
+                                format!(
+                                    "At most {MAX_PRODUCER_TRANSPORTS} producer transports can be created"
+                                ),
+                            ));
+                            return;
+                        }
+                    }
+                    TransportDirection::Consumer => {
+                        // Consumer transport is created once, repeated requests get the same one
+                        if let Some(transport) = &self.transports.consumer {
+                            address.do_send(ServerMessage::TransportCreated {
+                                request_id,
+                                direction,
+                                transport_options: transport_options(transport),
+                            });
+                            return;
+                        }
+                    }
+                }
+
+                let room = self.room.clone();
+                actix::spawn(async move {
+                    match create_transport(&room, direction).await {
+                        Ok(transport) => {
+                            println!(
+                                "[participant_id {participant_id}] {direction:?} transport created: {}",
+                                transport.id()
+                            );
+                            address.do_send(InternalMessage::SaveTransport {
+                                request_id,
+                                direction,
+                                transport,
+                            });
+                        }
+                        Err(error) => {
+                            eprintln!("[participant_id {participant_id}] {error}");
+                            address.do_send(ServerMessage::error(
+                                Some(action),
+                                request_id,
+                                ErrorCode::TransportCreateFailed,
+                                error,
+                            ));
+                        }
+                    }
+                });
+            }
+            ClientMessage::ConnectProducerTransport {
+                transport_id,
+                dtls_parameters,
+            } => {
                 let participant_id = self.id;
                 let address = ctx.address();
-                let transport = self.transports.producer.clone();
+                let Some(transport) = self.transports.producer(transport_id).cloned() else {
+                    Self::transport_not_found(
+                        ctx,
+                        action,
+                        request_id,
+                        TransportDirection::Producer,
+                        transport_id,
+                    );
+                    return;
+                };
                 // Establish connection for producer transport using DTLS parameters received
                 // from the client, but doing so in a background task since this handler is
                 // synchronous
@@ -241,58 +804,191 @@ impl Handler<ClientMessage> for ParticipantConnection {
                         .await
                     {
                         Ok(_) => {
-                            address.do_send(ServerMessage::ConnectedProducerTransport);
+                            address
+                                .do_send(ServerMessage::ConnectedProducerTransport { request_id });
                             println!(
                                 "[participant_id {participant_id}] Producer transport connected"
                             );
                         }
                         Err(error) => {
                             eprintln!("Failed to connect producer transport: {error}");
-                            address.do_send(InternalMessage::Stop);
+                            address.do_send(ServerMessage::error(
+                                Some(action),
+                                request_id,
+                                ErrorCode::TransportConnectFailed,
+                                format!("Failed to connect producer transport: {error}"),
+                            ));
+                        }
+                    }
+                });
+            }
+            ClientMessage::RestartIce { transport_id } => {
+                let transport = self
+                    .transports
+                    .iter()
+                    .find(|transport| transport.id() == transport_id)
+                    .cloned();
+                let Some(transport) = transport else {
+                    ctx.address().do_send(ServerMessage::error(
+                        Some(action),
+                        request_id,
+                        ErrorCode::TransportNotFound,
+                        format!("Transport {transport_id} doesn't exist"),
+                    ));
+                    return;
+                };
+
+                let participant_id = self.id;
+                let address = ctx.address();
+                actix::spawn(async move {
+                    match transport.restart_ice().await {
+                        Ok(ice_parameters) => {
+                            println!(
+                                "[participant_id {participant_id}] ICE of transport {transport_id} restarted"
+                            );
+                            address.do_send(ServerMessage::IceRestarted {
+                                request_id,
+                                transport_id,
+                                ice_parameters,
+                            });
+                        }
+                        Err(error) => {
+                            eprintln!(
+                                "[participant_id {participant_id}] Failed to restart ICE of transport {transport_id}: {error}"
+                            );
+                            address.do_send(ServerMessage::error(
+                                Some(action),
+                                request_id,
+                                ErrorCode::IceRestartFailed,
+                                format!("Failed to restart ICE: {error}"),
+                            ));
                         }
                     }
                 });
             }
             ClientMessage::Produce {
+                transport_id,
                 kind,
                 rtp_parameters,
+                source,
             } => {
                 let participant_id = self.id;
-                let address = ctx.address();
-                let transport = self.transports.producer.clone();
+                // Producer must be saved even if connection is lost meanwhile
+                let address = self.address.clone();
+                let Some(transport) = self.transports.producer(transport_id).cloned() else {
+                    Self::transport_not_found(
+                        ctx,
+                        action,
+                        request_id,
+                        TransportDirection::Producer,
+                        transport_id,
+                    );
+                    return;
+                };
+                let source = source.unwrap_or_else(|| ProducerSource::default_for(kind));
+                if source.kind() != kind {
+                    address.do_send(ServerMessage::error(
+                        Some(action),
+                        request_id,
+                        ErrorCode::ProduceFailed,
+                        format!("{source:?} can't be the source of {kind:?} producer"),
+                    ));
+                    return;
+                }
                 let room = self.room.clone();
                 // Use producer transport to create a new producer on the server with given RTP
-                // parameters
+                // parameters, source is kept in app data so that room can tell screen share from
+                // the camera
                 actix::spawn(async move {
-                    match transport
-                        .produce(ProducerOptions::new(kind, rtp_parameters))
-                        .await
-                    {
+                    let mut options = ProducerOptions::new(kind, rtp_parameters);
+                    options.app_data = AppData::new(source);
+
+                    match transport.produce(options).await {
                         Ok(producer) => {
                             let id = producer.id();
-                            address.do_send(ServerMessage::Produced { id });
+                            producer
+                                .on_score({
+                                    let address = address.clone();
+
+                                    move |score| {
+                                        address.do_send(InternalMessage::ProducerScore(
+                                            id,
+                                            score.to_vec(),
+                                        ));
+                                    }
+                                })
+                                .detach();
+                            address.do_send(ServerMessage::Produced { request_id, id });
                             // Add producer to the room so that others can consume it
-                            room.add_producer(participant_id, producer.clone());
+                            room.add_producer(participant_id, producer.clone()).await;
                             // Producer is stored in a hashmap since if we don't do it, it will
                             // get destroyed as soon as its instance goes out out scope
                             address.do_send(InternalMessage::SaveProducer(producer));
                             println!(
-                                "[participant_id {participant_id}] {kind:?} producer created: {id}"
+                                "[participant_id {participant_id}] {source:?} producer created: {id}"
                             );
                         }
                         Err(error) => {
                             eprintln!(
                                 "[participant_id {participant_id}] Failed to create {kind:?} producer: {error}"
                             );
-                            address.do_send(InternalMessage::Stop);
+                            address.do_send(ServerMessage::error(
+                                Some(action),
+                                request_id,
+                                ErrorCode::ProduceFailed,
+                                format!("Failed to create {kind:?} producer: {error}"),
+                            ));
                         }
                     }
                 });
             }
+            ClientMessage::PauseProducer { producer_id } => {
+                self.set_producer_paused(ctx, action, request_id, producer_id, true);
+            }
+            ClientMessage::ResumeProducer { producer_id } => {
+                self.set_producer_paused(ctx, action, request_id, producer_id, false);
+            }
+            ClientMessage::CloseProducer { producer_id } => {
+                let index = self
+                    .producers
+                    .iter()
+                    .position(|producer| producer.id() == producer_id);
+                let Some(index) = index else {
+                    ctx.address().do_send(ServerMessage::error(
+                        Some(action),
+                        request_id,
+                        ErrorCode::ProducerNotFound,
+                        format!("Producer {producer_id} doesn't exist"),
+                    ));
+                    return;
+                };
+
+                // Producer is closed once both the room and the connection drop it
+                if let Err(error) = self.room.close_producer(&self.id, &producer_id) {
+                    eprintln!("[participant_id {}] {error}", self.id);
+                }
+                let _ = self.producers.remove(index);
+                println!("[participant_id {}] Producer {producer_id} closed", self.id);
+
+                ctx.address().do_send(ServerMessage::ProducerRemoved {
+                    request_id,
+                    participant_id: self.id,
+                    producer_id,
+                });
+            }
             ClientMessage::ConnectConsumerTransport { dtls_parameters } => {
                 let participant_id = self.id;
                 let address = ctx.address();
-                let transport = self.transports.consumer.clone();
+                let Some(transport) = self.transports.consumer.clone() else {
+                    Self::transport_not_found(
+                        ctx,
+                        action,
+                        request_id,
+                        TransportDirection::Consumer,
+                        None,
+                    );
+                    return;
+                };
                 // The same as producer transport, but for consumer transport
                 actix::spawn(async move {
                     match transport
@@ -300,7 +996,8 @@ impl Handler<ClientMessage> for ParticipantConnection {
                         .await
                     {
                         Ok(_) => {
-                            address.do_send(ServerMessage::ConnectedConsumerTransport);
+                            address
+                                .do_send(ServerMessage::ConnectedConsumerTransport { request_id });
                             println!(
                                 "[participant_id {participant_id}] Consumer transport connected"
                             );
@@ -309,15 +1006,30 @@ impl Handler<ClientMessage> for ParticipantConnection {
                             eprintln!(
                                 "[participant_id {participant_id}] Failed to connect consumer transport: {error}"
                             );
-                            address.do_send(InternalMessage::Stop);
+                            address.do_send(ServerMessage::error(
+                                Some(action),
+                                request_id,
+                                ErrorCode::TransportConnectFailed,
+                                format!("Failed to connect consumer transport: {error}"),
+                            ));
                         }
                     }
                 });
             }
             ClientMessage::Consume { producer_id } => {
                 let participant_id = self.id;
-                let address = ctx.address();
-                let transport = self.transports.consumer.clone();
+                // Consumer and its event handlers outlive this connection when session is resumed
+                let address = self.address.clone();
+                let Some(transport) = self.transports.consumer.clone() else {
+                    Self::transport_not_found(
+                        ctx,
+                        action,
+                        request_id,
+                        TransportDirection::Consumer,
+                        None,
+                    );
+                    return;
+                };
                 let rtp_capabilities = match self.client_rtp_capabilities.clone() {
                     Some(rtp_capabilities) => rtp_capabilities,
                     None => {
@@ -325,6 +1037,12 @@ impl Handler<ClientMessage> for ParticipantConnection {
                             "[participant_id {participant_id}] Client should send RTP capabilities before \
                             consuming"
                         );
+                        address.do_send(ServerMessage::error(
+                            Some(action),
+                            request_id,
+                            ErrorCode::MissingRtpCapabilities,
+                            "Client should send RTP capabilities with Init before consuming",
+                        ));
                         return;
                     }
                 };
@@ -338,8 +1056,54 @@ impl Handler<ClientMessage> for ParticipantConnection {
                         Ok(consumer) => {
                             let id = consumer.id();
                             let kind = consumer.kind();
+                            // Let the client know which simulcast/SVC layers it actually gets
+                            consumer
+                                .on_layers_change({
+                                    let address = address.clone();
+
+                                    move |layers| {
+                                        address.do_send(ServerMessage::LayersChanged {
+                                            consumer_id: id,
+                                            layers: *layers,
+                                        });
+                                    }
+                                })
+                                .detach();
+                            consumer
+                                .on_score({
+                                    let address = address.clone();
+
+                                    move |score| {
+                                        address.do_send(InternalMessage::ConsumerScore(
+                                            id,
+                                            score.clone(),
+                                        ));
+                                    }
+                                })
+                                .detach();
+                            // Consumer is closed by mediasoup together with its producer or
+                            // transport, forget about it then
+                            consumer
+                                .on_producer_close({
+                                    let address = address.clone();
+
+                                    move || {
+                                        address.do_send(InternalMessage::RemoveConsumer(id));
+                                    }
+                                })
+                                .detach();
+                            consumer
+                                .on_transport_close({
+                                    let address = address.clone();
+
+                                    move || {
+                                        address.do_send(InternalMessage::RemoveConsumer(id));
+                                    }
+                                })
+                                .detach();
                             let rtp_parameters = consumer.rtp_parameters().clone();
                             address.do_send(ServerMessage::Consumed {
+                                request_id,
                                 id,
                                 producer_id,
                                 kind,
@@ -356,14 +1120,191 @@ impl Handler<ClientMessage> for ParticipantConnection {
                             eprintln!(
                                 "[participant_id {participant_id}] Failed to create consumer: {error}"
                             );
-                            address.do_send(InternalMessage::Stop);
+                            address.do_send(ServerMessage::error(
+                                Some(action),
+                                request_id,
+                                ErrorCode::ConsumeFailed,
+                                format!(
+                                    "Failed to create consumer for producer {producer_id}: {error}"
+                                ),
+                            ));
+                        }
+                    }
+                });
+            }
+            ClientMessage::ProduceData {
+                transport_id,
+                sctp_stream_parameters,
+                label,
+                protocol,
+            } => {
+                let participant_id = self.id;
+                // Data producer must be saved even if connection is lost meanwhile
+                let address = self.address.clone();
+                let Some(transport) = self.transports.producer(transport_id).cloned() else {
+                    Self::transport_not_found(
+                        ctx,
+                        action,
+                        request_id,
+                        TransportDirection::Producer,
+                        transport_id,
+                    );
+                    return;
+                };
+                let room = self.room.clone();
+                actix::spawn(async move {
+                    let mut options = DataProducerOptions::new_sctp(sctp_stream_parameters);
+                    options.label = label;
+                    options.protocol = protocol;
+
+                    match transport.produce_data(options).await {
+                        Ok(data_producer) => {
+                            let id = data_producer.id();
+                            address.do_send(ServerMessage::DataProduced { request_id, id });
+                            // Add data producer to the room so that others can consume it
+                            room.add_data_producer(participant_id, data_producer.clone());
+                            address.do_send(InternalMessage::SaveDataProducer(data_producer));
+                            println!(
+                                "[participant_id {participant_id}] Data producer created: {id}"
+                            );
+                        }
+                        Err(error) => {
+                            eprintln!(
+                                "[participant_id {participant_id}] Failed to create data producer: {error}"
+                            );
+                            address.do_send(ServerMessage::error(
+                                Some(action),
+                                request_id,
+                                ErrorCode::ProduceDataFailed,
+                                format!("Failed to create data producer: {error}"),
+                            ));
+                        }
+                    }
+                });
+            }
+            ClientMessage::ConsumeData { data_producer_id } => {
+                let participant_id = self.id;
+                // Data consumer and its event handlers outlive this connection when session is
+                // resumed
+                let address = self.address.clone();
+                let Some(transport) = self.transports.consumer.clone() else {
+                    Self::transport_not_found(
+                        ctx,
+                        action,
+                        request_id,
+                        TransportDirection::Consumer,
+                        None,
+                    );
+                    return;
+                };
+                actix::spawn(async move {
+                    match transport
+                        .consume_data(DataConsumerOptions::new_sctp(data_producer_id))
+                        .await
+                    {
+                        Ok(data_consumer) => {
+                            let id = data_consumer.id();
+                            // Data consumer is closed by mediasoup together with its data
+                            // producer or transport, forget about it then
+                            data_consumer
+                                .on_data_producer_close({
+                                    let address = address.clone();
+
+                                    move || {
+                                        address.do_send(InternalMessage::RemoveDataConsumer(id));
+                                    }
+                                })
+                                .detach();
+                            data_consumer
+                                .on_transport_close({
+                                    let address = address.clone();
+
+                                    move || {
+                                        address.do_send(InternalMessage::RemoveDataConsumer(id));
+                                    }
+                                })
+                                .detach();
+                            address.do_send(ServerMessage::DataConsumed {
+                                request_id,
+                                id,
+                                data_producer_id,
+                                sctp_stream_parameters: data_consumer.sctp_stream_parameters(),
+                                label: data_consumer.label().clone(),
+                                protocol: data_consumer.protocol().clone(),
+                            });
+                            address.do_send(InternalMessage::SaveDataConsumer(data_consumer));
+                            println!(
+                                "[participant_id {participant_id}] Data consumer created: {id}"
+                            );
+                        }
+                        Err(error) => {
+                            eprintln!(
+                                "[participant_id {participant_id}] Failed to create data consumer: {error}"
+                            );
+                            address.do_send(ServerMessage::error(
+                                Some(action),
+                                request_id,
+                                ErrorCode::ConsumeDataFailed,
+                                format!(
+                                    "Failed to create data consumer for data producer {data_producer_id}: {error}"
+                                ),
+                            ));
                         }
                     }
                 });
             }
+            ClientMessage::CloseDataProducer { data_producer_id } => {
+                let index = self
+                    .data_producers
+                    .iter()
+                    .position(|data_producer| data_producer.id() == data_producer_id);
+                let Some(index) = index else {
+                    ctx.address().do_send(ServerMessage::error(
+                        Some(action),
+                        request_id,
+                        ErrorCode::DataProducerNotFound,
+                        format!("Data producer {data_producer_id} doesn't exist"),
+                    ));
+                    return;
+                };
+
+                // Data producer is closed once both the room and the connection drop it, others
+                // are notified about it then
+                if let Err(error) = self.room.close_data_producer(&self.id, &data_producer_id) {
+                    eprintln!("[participant_id {}] {error}", self.id);
+                }
+                let _ = self.data_producers.remove(index);
+                println!(
+                    "[participant_id {}] Data producer {data_producer_id} closed",
+                    self.id
+                );
+
+                ctx.address().do_send(ServerMessage::DataProducerRemoved {
+                    request_id,
+                    participant_id: self.id,
+                    data_producer_id,
+                });
+            }
+            ClientMessage::CloseDataConsumer { data_consumer_id } => {
+                // Dropping the last instance closes the data consumer
+                if self.data_consumers.remove(&data_consumer_id).is_some() {
+                    println!(
+                        "[participant_id {}] Data consumer {data_consumer_id} closed",
+                        self.id
+                    );
+                } else {
+                    ctx.address().do_send(ServerMessage::error(
+                        Some(action),
+                        request_id,
+                        ErrorCode::DataConsumerNotFound,
+                        format!("Data consumer {data_consumer_id} doesn't exist"),
+                    ));
+                }
+            }
             ClientMessage::ConsumerResume { id } => {
                 if let Some(consumer) = self.consumers.get(&id).cloned() {
                     let participant_id = self.id;
+                    let address = ctx.address();
                     actix::spawn(async move {
                         match consumer.resume().await {
                             Ok(_) => {
@@ -373,6 +1314,18 @@ impl Handler<ClientMessage> for ParticipantConnection {
                                     consumer.kind(),
                                     consumer.id(),
                                 );
+
+                                // Don't make the new viewer wait for the next key frame
+                                if consumer.kind() == MediaKind::Video {
+                                    if let Err(error) = consumer.request_key_frame().await {
+                                        println!(
+                                            "[participant_id {}] Failed to request key frame for consumer {}: {}",
+                                            participant_id,
+                                            consumer.id(),
+                                            error,
+                                        );
+                                    }
+                                }
                             }
                             Err(error) => {
                                 println!(
@@ -382,47 +1335,221 @@ impl Handler<ClientMessage> for ParticipantConnection {
                                     consumer.id(),
                                     error,
                                 );
+                                address.do_send(ServerMessage::error(
+                                    Some(action),
+                                    request_id,
+                                    ErrorCode::ConsumerResumeFailed,
+                                    format!("Failed to resume consumer {}: {error}", consumer.id()),
+                                ));
+                            }
+                        }
+                    });
+                } else {
+                    ctx.address().do_send(ServerMessage::error(
+                        Some(action),
+                        request_id,
+                        ErrorCode::ConsumerNotFound,
+                        format!("Consumer {id} doesn't exist"),
+                    ));
+                }
+            }
+            ClientMessage::ConsumerPause { id } => {
+                if let Some(consumer) = self.consumers.get(&id).cloned() {
+                    let participant_id = self.id;
+                    let address = ctx.address();
+                    actix::spawn(async move {
+                        match consumer.pause().await {
+                            Ok(_) => {
+                                println!(
+                                    "[participant_id {}] Successfully paused {:?} consumer {}",
+                                    participant_id,
+                                    consumer.kind(),
+                                    consumer.id(),
+                                );
+                            }
+                            Err(error) => {
+                                println!(
+                                    "[participant_id {}] Failed to pause {:?} consumer {}: {}",
+                                    participant_id,
+                                    consumer.kind(),
+                                    consumer.id(),
+                                    error,
+                                );
+                                address.do_send(ServerMessage::error(
+                                    Some(action),
+                                    request_id,
+                                    ErrorCode::ConsumerPauseFailed,
+                                    format!("Failed to pause consumer {}: {error}", consumer.id()),
+                                ));
                             }
                         }
                     });
+                } else {
+                    ctx.address().do_send(ServerMessage::error(
+                        Some(action),
+                        request_id,
+                        ErrorCode::ConsumerNotFound,
+                        format!("Consumer {id} doesn't exist"),
+                    ));
                 }
             }
-            ClientMessage::StartRecording { output_name } => {
+            ClientMessage::ConsumerClose { id } => {
+                // Dropping the last instance closes the consumer
+                if self.consumers.remove(&id).is_some() {
+                    self.room.remove_consumer(&self.id, &id);
+                    self.pending_scores.consumers.remove(&id);
+                    println!("[participant_id {}] Consumer {id} closed", self.id);
+                } else {
+                    ctx.address().do_send(ServerMessage::error(
+                        Some(action),
+                        request_id,
+                        ErrorCode::ConsumerNotFound,
+                        format!("Consumer {id} doesn't exist"),
+                    ));
+                }
+            }
+            ClientMessage::SetPreferredLayers {
+                consumer_id,
+                spatial_layer,
+                temporal_layer,
+            } => {
+                if let Some(consumer) = self.consumers.get(&consumer_id).cloned() {
+                    let participant_id = self.id;
+                    let address = ctx.address();
+                    actix::spawn(async move {
+                        let layers = ConsumerLayers {
+                            spatial_layer,
+                            temporal_layer,
+                        };
+                        if let Err(error) = consumer.set_preferred_layers(layers).await {
+                            println!(
+                                "[participant_id {}] Failed to set preferred layers of consumer {}: {}",
+                                participant_id, consumer_id, error,
+                            );
+                            address.do_send(ServerMessage::error(
+                                Some(action),
+                                request_id,
+                                ErrorCode::SetPreferredLayersFailed,
+                                format!(
+                                    "Failed to set preferred layers of consumer {consumer_id}: {error}"
+                                ),
+                            ));
+                        }
+                    });
+                } else {
+                    ctx.address().do_send(ServerMessage::error(
+                        Some(action),
+                        request_id,
+                        ErrorCode::ConsumerNotFound,
+                        format!("Consumer {consumer_id} doesn't exist"),
+                    ));
+                }
+            }
+            ClientMessage::GetStats => {
+                let participant_id = self.id;
+                let address = ctx.address();
+                let room = self.room.clone();
+                actix::spawn(async move {
+                    match room.get_participant_stats(&participant_id).await {
+                        Ok(Some(stats)) => {
+                            address.do_send(ServerMessage::Stats { request_id, stats });
+                        }
+                        Ok(None) => {
+                            address.do_send(ServerMessage::error(
+                                Some(action),
+                                request_id,
+                                ErrorCode::StatsFailed,
+                                "Participant is not in the room anymore",
+                            ));
+                        }
+                        Err(error) => {
+                            eprintln!("[participant_id {participant_id}] {error}");
+                            address.do_send(ServerMessage::error(
+                                Some(action),
+                                request_id,
+                                ErrorCode::StatsFailed,
+                                error,
+                            ));
+                        }
+                    }
+                });
+            }
+            ClientMessage::StartRecording { output_name, mode } => {
                 let participant_id = self.id;
+                let address = ctx.address();
                 let mut room = self.room.clone();
                 actix::spawn(async move {
-                    match room.start_recording(&participant_id, &output_name).await {
-                        Ok(_) => {
+                    let output_name = output_name.as_deref();
+                    let result = match mode {
+                        RecordingMode::Participant => {
+                            room.start_recording(&participant_id, output_name).await
+                        }
+                        RecordingMode::Room => {
+                            room.start_room_recording(&participant_id, output_name)
+                                .await
+                        }
+                    };
+                    match result {
+                        Ok(recording_id) => {
                             println!(
-                                "[participant_id {}] Successfully started recording",
-                                participant_id,
+                                "[participant_id {}] Successfully started recording {}",
+                                participant_id, recording_id,
                             );
+                            address.do_send(ServerMessage::RecordingStarted {
+                                request_id,
+                                participant_id,
+                                mode,
+                                recording_id,
+                            });
                         }
                         Err(error) => {
                             println!(
                                 "[participant_id {}] Failed to start recording {}",
                                 participant_id, error,
                             );
+                            address.do_send(ServerMessage::error(
+                                Some(action),
+                                request_id,
+                                ErrorCode::RecordingFailed,
+                                format!("Failed to start recording: {error}"),
+                            ));
                         }
                     }
                 });
             }
-            ClientMessage::StopRecording {} => {
+            ClientMessage::StopRecording { mode } => {
                 let participant_id = self.id;
+                let address = ctx.address();
                 let mut room = self.room.clone();
                 actix::spawn(async move {
-                    match room.stop_recording(&participant_id).await {
-                        Ok(_) => {
+                    let result = match mode {
+                        RecordingMode::Participant => room.stop_recording(&participant_id).await,
+                        RecordingMode::Room => room.stop_room_recording(&participant_id).await,
+                    };
+                    match result {
+                        Ok(summary) => {
                             println!(
                                 "[participant_id {}] Successfully stopped recording",
                                 participant_id,
                             );
+                            address.do_send(ServerMessage::recording(
+                                request_id,
+                                participant_id,
+                                mode,
+                                &RecordingEvent::Stopped(summary),
+                            ));
                         }
                         Err(error) => {
                             println!(
                                 "[participant_id {}] Failed to stop recording {}",
                                 participant_id, error,
                             );
+                            address.do_send(ServerMessage::error(
+                                Some(action),
+                                request_id,
+                                ErrorCode::RecordingFailed,
+                                format!("Failed to stop recording: {error}"),
+                            ));
                         }
                     }
                 });
@@ -450,15 +1577,111 @@ impl Handler<InternalMessage> for ParticipantConnection {
     fn handle(&mut self, message: InternalMessage, ctx: &mut Self::Context) {
         match message {
             InternalMessage::Stop => {
+                self.closing = true;
                 ctx.stop();
             }
+            InternalMessage::SaveTransport {
+                request_id,
+                direction,
+                transport,
+            } => {
+                // Concurrent request may have created consumer transport already, the extra one
+                // is closed when dropped here
+                let existing = match direction {
+                    TransportDirection::Producer => None,
+                    TransportDirection::Consumer => self.transports.consumer.clone(),
+                };
+                let transport = match existing {
+                    Some(existing) => existing,
+                    None => {
+                        match direction {
+                            TransportDirection::Producer => {
+                                self.transports.producers.push(transport.clone());
+                            }
+                            TransportDirection::Consumer => {
+                                self.transports.consumer.replace(transport.clone());
+                            }
+                        }
+                        self.room.add_transport(self.id, transport.clone());
+                        self.attach_transport_handlers(&transport, &ctx.address());
+                        transport
+                    }
+                };
+
+                ctx.address().do_send(ServerMessage::TransportCreated {
+                    request_id,
+                    direction,
+                    transport_options: transport_options(&transport),
+                });
+            }
             InternalMessage::SaveProducer(producer) => {
                 // Retain producer to prevent it from being destroyed
                 self.producers.push(producer);
             }
             InternalMessage::SaveConsumer(consumer) => {
+                // Producer may have been closed already while consumer was being created
+                if consumer.closed() {
+                    ctx.address().do_send(ServerMessage::ConsumerClosed {
+                        consumer_id: consumer.id(),
+                    });
+                    return;
+                }
+                self.room.add_consumer(self.id, consumer.clone());
                 self.consumers.insert(consumer.id(), consumer);
             }
+            InternalMessage::ProducerScore(producer_id, score) => {
+                self.room
+                    .set_producer_score(&self.id, producer_id, score.clone());
+                self.pending_scores.producers.insert(producer_id, score);
+            }
+            InternalMessage::ConsumerScore(consumer_id, score) => {
+                // Score may arrive right after the consumer was closed
+                if self.consumers.contains_key(&consumer_id) {
+                    self.room
+                        .set_consumer_score(&self.id, consumer_id, score.clone());
+                    self.pending_scores.consumers.insert(consumer_id, score);
+                }
+            }
+            InternalMessage::RemoveConsumer(consumer_id) => {
+                // Consumers that are not saved yet are handled in `SaveConsumer`
+                if self.consumers.remove(&consumer_id).is_some() {
+                    self.room.remove_consumer(&self.id, &consumer_id);
+                    self.pending_scores.consumers.remove(&consumer_id);
+                    println!(
+                        "[participant_id {}] Consumer {consumer_id} was closed",
+                        self.id
+                    );
+                    ctx.address()
+                        .do_send(ServerMessage::ConsumerClosed { consumer_id });
+                }
+            }
+            InternalMessage::SaveDataProducer(data_producer) => {
+                // Retain data producer to prevent it from being destroyed
+                self.data_producers.push(data_producer);
+            }
+            InternalMessage::SaveDataConsumer(data_consumer) => {
+                // Data producer may have been closed already while data consumer was being
+                // created
+                if data_consumer.closed() {
+                    ctx.address().do_send(ServerMessage::DataConsumerClosed {
+                        data_consumer_id: data_consumer.id(),
+                    });
+                    return;
+                }
+                self.data_consumers
+                    .insert(data_consumer.id(), data_consumer);
+            }
+            InternalMessage::RemoveDataConsumer(data_consumer_id) => {
+                // Data consumers that are not saved yet are handled in `SaveDataConsumer`
+                if self.data_consumers.remove(&data_consumer_id).is_some() {
+                    println!(
+                        "[participant_id {}] Data consumer {data_consumer_id} was closed",
+                        self.id
+                    );
+                    ctx.address()
+                        .do_send(ServerMessage::DataConsumerClosed { data_consumer_id });
+                }
+            }
         }
     }
 }
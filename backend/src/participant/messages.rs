@@ -1,9 +1,20 @@
+use crate::participant::session::SessionToken;
 use crate::participant::ParticipantId;
-use crate::room::RoomId;
+use crate::recording::{RecordingEvent, RecordingMode};
+use crate::room::{ParticipantStats, ProducerSource, RoomId};
 use actix::prelude::*;
+use mediasoup::consumer::ConsumerScore;
 use mediasoup::prelude::*;
+use mediasoup::producer::ProducerScore;
+use mediasoup::sctp_parameters::{SctpParameters, SctpStreamParameters};
 use serde::{Deserialize, Serialize};
 
+/// Arbitrary ID chosen by the client for a request, it is echoed back in the response so that
+/// the client can match responses with requests even when several of them are in flight
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct RequestId(String);
+
 /// Data structure containing all the necessary information about transport options required
 /// from the server to establish transport connection on the client
 #[derive(Serialize)]
@@ -13,6 +24,65 @@ pub struct TransportOptions {
     pub dtls_parameters: DtlsParameters,
     pub ice_candidates: Vec<IceCandidate>,
     pub ice_parameters: IceParameters,
+    /// Present since SCTP is enabled for data channels
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sctp_parameters: Option<SctpParameters>,
+}
+
+/// Direction of the transport from the client's point of view, producer transport sends media
+/// to the server and consumer transport receives it
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TransportDirection {
+    Producer,
+    Consumer,
+}
+
+/// Stable error codes sent to the client in `ServerMessage::Error`
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize)]
+pub enum ErrorCode {
+    /// Message could not be parsed as a `ClientMessage`
+    InvalidMessage,
+    /// Client tried to consume before sending its RTP capabilities with `Init`
+    MissingRtpCapabilities,
+    /// Producer or consumer transport could not be created on the server, or participant has
+    /// too many producer transports already
+    TransportCreateFailed,
+    /// Producer or consumer transport failed to connect
+    TransportConnectFailed,
+    /// Transport with requested ID or direction doesn't exist or doesn't belong to the
+    /// participant
+    TransportNotFound,
+    /// ICE of the transport could not be restarted
+    IceRestartFailed,
+    /// Producer could not be created on the server
+    ProduceFailed,
+    /// Consumer could not be created on the server
+    ConsumeFailed,
+    /// Data producer could not be created on the server
+    ProduceDataFailed,
+    /// Data consumer could not be created on the server
+    ConsumeDataFailed,
+    /// Consumer with requested ID doesn't exist
+    ConsumerNotFound,
+    /// Consumer could not be resumed
+    ConsumerResumeFailed,
+    /// Consumer could not be paused
+    ConsumerPauseFailed,
+    /// Producer with requested ID doesn't exist or doesn't belong to the participant
+    ProducerNotFound,
+    /// Data producer with requested ID doesn't exist or doesn't belong to the participant
+    DataProducerNotFound,
+    /// Data consumer with requested ID doesn't exist
+    DataConsumerNotFound,
+    /// Producer could not be paused or resumed
+    ProducerPauseFailed,
+    /// Preferred layers of the consumer could not be set
+    SetPreferredLayersFailed,
+    /// Recording could not be started or stopped
+    RecordingFailed,
+    /// Stats could not be collected
+    StatsFailed,
 }
 
 /// Server messages sent to the client
@@ -21,54 +91,318 @@ pub struct TransportOptions {
 #[rtype(result = "()")]
 #[allow(clippy::large_enum_variant)]
 pub enum ServerMessage {
-    /// Initialization message with consumer/producer transport options and Router's RTP
-    /// capabilities necessary to establish WebRTC transport connection client-side
+    /// Initialization message with Router's RTP capabilities necessary to establish WebRTC
+    /// transport connection client-side. Consumer/producer transport options are only included
+    /// when the client asked for them with `bundleTransports` query parameter or when the
+    /// transports exist already in the resumed session, otherwise they are created with
+    /// `CreateTransport`.
+    ///
+    /// `session_token` allows the client to reconnect to the same session after the WebSocket
+    /// connection was lost, it is absent when resumption is disabled. When `resumed` is `true`
+    /// transports, producers and consumers created by the client before are still in place.
     #[serde(rename_all = "camelCase")]
     Init {
         room_id: RoomId,
-        consumer_transport_options: TransportOptions,
-        producer_transport_options: TransportOptions,
+        participant_id: ParticipantId,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        session_token: Option<SessionToken>,
+        resumed: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        consumer_transport_options: Option<TransportOptions>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        producer_transport_options: Option<TransportOptions>,
         router_rtp_capabilities: RtpCapabilitiesFinalized,
     },
-    /// Notification that new producer was added to the room
+    /// Notification that new producer was added to the room, it may be paused already when it
+    /// existed before the client joined
     #[serde(rename_all = "camelCase")]
     ProducerAdded {
         participant_id: ParticipantId,
         name: String,
         producer_id: ProducerId,
+        source: ProducerSource,
+        paused: bool,
     },
-    /// Notification that producer was removed from the room
+    /// Notification that producer was removed from the room, `request_id` is only present in
+    /// the response to the participant's own `CloseProducer` request
     #[serde(rename_all = "camelCase")]
     ProducerRemoved {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        request_id: Option<RequestId>,
+        participant_id: ParticipantId,
+        producer_id: ProducerId,
+    },
+    /// Notification that producer was paused (for instance microphone was muted),
+    /// `request_id` is only present in the response to the participant's own request
+    #[serde(rename_all = "camelCase")]
+    ProducerPaused {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        request_id: Option<RequestId>,
+        participant_id: ParticipantId,
+        producer_id: ProducerId,
+    },
+    /// Notification that producer was resumed, `request_id` is only present in the response to
+    /// the participant's own request
+    #[serde(rename_all = "camelCase")]
+    ProducerResumed {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        request_id: Option<RequestId>,
         participant_id: ParticipantId,
         producer_id: ProducerId,
     },
     /// Notification that producer transport was connected successfully (in case of error
-    /// connection is just dropped, in real-world application you probably want to handle it
-    /// better)
-    ConnectedProducerTransport,
+    /// `Error` is sent instead)
+    #[serde(rename_all = "camelCase")]
+    ConnectedProducerTransport {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        request_id: Option<RequestId>,
+    },
+    /// Response to `CreateTransport` with options client needs to create the transport
+    #[serde(rename_all = "camelCase")]
+    TransportCreated {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        request_id: Option<RequestId>,
+        direction: TransportDirection,
+        transport_options: TransportOptions,
+    },
+    /// Response to `RestartIce` with new ICE parameters that client should use for the transport
+    #[serde(rename_all = "camelCase")]
+    IceRestarted {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        request_id: Option<RequestId>,
+        transport_id: TransportId,
+        ice_parameters: IceParameters,
+    },
+    /// Notification that ICE or DTLS connection of the transport was lost, client can try to
+    /// recover with `RestartIce` unless the reason says it was closed or DTLS failed
+    #[serde(rename_all = "camelCase")]
+    TransportDisconnected {
+        transport_id: TransportId,
+        reason: String,
+    },
     /// Notification that producer was created on the server
     #[serde(rename_all = "camelCase")]
-    Produced { id: ProducerId },
+    Produced {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        request_id: Option<RequestId>,
+        id: ProducerId,
+    },
+    /// Notification that data producer was created on the server
+    #[serde(rename_all = "camelCase")]
+    DataProduced {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        request_id: Option<RequestId>,
+        id: DataProducerId,
+    },
+    /// Notification that new data producer (data channel of another participant) was added to
+    /// the room
+    #[serde(rename_all = "camelCase")]
+    DataProducerAdded {
+        participant_id: ParticipantId,
+        name: String,
+        data_producer_id: DataProducerId,
+        label: String,
+        protocol: String,
+    },
+    /// Notification that data producer was removed from the room, `request_id` is only present
+    /// in the response to the participant's own `CloseDataProducer` request
+    #[serde(rename_all = "camelCase")]
+    DataProducerRemoved {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        request_id: Option<RequestId>,
+        participant_id: ParticipantId,
+        data_producer_id: DataProducerId,
+    },
     /// Notification that consumer transport was connected successfully (in case of error
-    /// connection is just dropped, in real-world application you probably want to handle it
-    /// better)
-    ConnectedConsumerTransport,
+    /// `Error` is sent instead)
+    #[serde(rename_all = "camelCase")]
+    ConnectedConsumerTransport {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        request_id: Option<RequestId>,
+    },
     /// Notification that consumer was successfully created server-side, client can resume
     /// the consumer after this
     #[serde(rename_all = "camelCase")]
     Consumed {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        request_id: Option<RequestId>,
         id: ConsumerId,
         producer_id: ProducerId,
         kind: MediaKind,
         rtp_parameters: RtpParameters,
     },
+    /// Notification that data consumer was successfully created server-side, with parameters
+    /// client needs to create its side of the data channel
+    #[serde(rename_all = "camelCase")]
+    DataConsumed {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        request_id: Option<RequestId>,
+        id: DataConsumerId,
+        data_producer_id: DataProducerId,
+        sctp_stream_parameters: Option<SctpStreamParameters>,
+        label: String,
+        protocol: String,
+    },
+    /// Notification that data consumer was closed on the server because its data producer or
+    /// transport was closed, client should close it too
+    #[serde(rename_all = "camelCase")]
+    DataConsumerClosed { data_consumer_id: DataConsumerId },
+    /// Notification about the loudest participant of the room and its volume in dBov (from
+    /// -127 to 0), sent periodically while anyone is speaking
+    #[serde(rename_all = "camelCase")]
+    ActiveSpeaker {
+        participant_id: ParticipantId,
+        volume: i8,
+    },
+    /// Notification that nobody in the room is speaking anymore
+    Silence,
+    /// Latest score of own producer for each of its streams (from 0 to 10), sent only when it
+    /// changes and at most every few seconds
+    #[serde(rename_all = "camelCase")]
+    ProducerScore {
+        producer_id: ProducerId,
+        score: Vec<ProducerScore>,
+    },
+    /// Latest score of own consumer (from 0 to 10), sent only when it changes and at most every
+    /// few seconds
+    #[serde(rename_all = "camelCase")]
+    ConsumerScore {
+        consumer_id: ConsumerId,
+        score: ConsumerScore,
+    },
+    /// Response to `GetStats` with stats of own transports, producers and consumers
+    #[serde(rename_all = "camelCase")]
+    Stats {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        request_id: Option<RequestId>,
+        stats: ParticipantStats,
+    },
+    /// Notification that consumer was closed on the server because its producer or transport
+    /// was closed, client should close it too
+    #[serde(rename_all = "camelCase")]
+    ConsumerClosed { consumer_id: ConsumerId },
+    /// Notification that the simulcast/SVC layers forwarded to the consumer have changed, `None`
+    /// means that no layer is being forwarded (for instance because producer is paused)
+    #[serde(rename_all = "camelCase")]
+    LayersChanged {
+        consumer_id: ConsumerId,
+        layers: Option<ConsumerLayers>,
+    },
+    /// Notification that recording of the participant (or of the whole room started by the
+    /// participant) was started, with the ID under which it is stored, `request_id` is only
+    /// present in the response to the participant's own request
+    #[serde(rename_all = "camelCase")]
+    RecordingStarted {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        request_id: Option<RequestId>,
+        participant_id: ParticipantId,
+        mode: RecordingMode,
+        recording_id: String,
+    },
+    /// Notification that recording was stopped by the participant and saved to `path`
+    #[serde(rename_all = "camelCase")]
+    RecordingStopped {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        request_id: Option<RequestId>,
+        participant_id: ParticipantId,
+        mode: RecordingMode,
+        recording_id: String,
+        path: String,
+        duration_ms: u64,
+        size_bytes: u64,
+    },
+    /// Notification that recording of the participant has failed and is not in progress anymore,
+    /// only sent for recordings that were started (failure to start is reported with `Error`)
+    #[serde(rename_all = "camelCase")]
+    RecordingFailed {
+        participant_id: ParticipantId,
+        mode: RecordingMode,
+        recording_id: String,
+        reason: String,
+    },
+    /// Notification that the room can't be used anymore (for instance because its worker has
+    /// died), connection is closed right after this message
+    #[serde(rename_all = "camelCase")]
+    RoomClosed { reason: String },
+    /// Notification that the request with given action has failed, `request_action` is `None`
+    /// when the request itself could not be parsed
+    #[serde(rename_all = "camelCase")]
+    Error {
+        request_action: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        request_id: Option<RequestId>,
+        code: ErrorCode,
+        message: String,
+    },
 }
 
-/// Client messages sent to the server
+impl ServerMessage {
+    /// Create message about recording event of the participant, `request_id` is only set for
+    /// the participant that requested the change
+    pub fn recording(
+        request_id: Option<RequestId>,
+        participant_id: ParticipantId,
+        mode: RecordingMode,
+        event: &RecordingEvent,
+    ) -> Self {
+        match event {
+            RecordingEvent::Started { recording_id } => Self::RecordingStarted {
+                request_id,
+                participant_id,
+                mode,
+                recording_id: recording_id.clone(),
+            },
+            RecordingEvent::Stopped(summary) => Self::RecordingStopped {
+                request_id,
+                participant_id,
+                mode,
+                recording_id: summary.recording_id.clone(),
+                path: summary.path.display().to_string(),
+                duration_ms: summary.duration.as_millis() as u64,
+                size_bytes: summary.size_bytes,
+            },
+            RecordingEvent::Failed {
+                recording_id,
+                reason,
+            } => Self::RecordingFailed {
+                participant_id,
+                mode,
+                recording_id: recording_id.clone(),
+                reason: reason.clone(),
+            },
+        }
+    }
+
+    /// Create `ServerMessage::Error` in response to the request with given action and ID
+    pub fn error(
+        request_action: Option<&str>,
+        request_id: Option<RequestId>,
+        code: ErrorCode,
+        message: impl Into<String>,
+    ) -> Self {
+        Self::Error {
+            request_action: request_action.map(str::to_owned),
+            request_id,
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+/// Client request, which is `ClientMessage` with optional `requestId` next to its `action`
 #[derive(Deserialize, Message)]
-#[serde(tag = "action")]
+#[serde(rename_all = "camelCase")]
 #[rtype(result = "()")]
+pub struct ClientRequest {
+    #[serde(default)]
+    pub request_id: Option<RequestId>,
+    #[serde(flatten)]
+    pub message: ClientMessage,
+}
+
+/// Client messages sent to the server
+#[derive(Deserialize)]
+#[serde(tag = "action")]
 pub enum ClientMessage {
     /// Client-side initialization with its RTP capabilities, in this simple case we expect
     /// those to match server Router's RTP capabilities
@@ -77,17 +411,52 @@ pub enum ClientMessage {
         name: String,
         rtp_capabilities: RtpCapabilities,
     },
-    /// Request to connect producer transport with client-side DTLS parameters
+    /// Request to create producer or consumer transport, responded with `TransportCreated`.
+    /// Every request creates a new producer transport (for instance for a screen share), while
+    /// existing consumer transport is returned if it was created already. View-only clients never
+    /// need to allocate a producer transport and the other way around.
     #[serde(rename_all = "camelCase")]
-    ConnectProducerTransport { dtls_parameters: DtlsParameters },
+    CreateTransport { direction: TransportDirection },
 
-    /// Request to produce a new audio or video track with specified RTP parameters
+    /// Request to connect producer transport with client-side DTLS parameters, the first
+    /// producer transport is used when `transport_id` is not specified
+    #[serde(rename_all = "camelCase")]
+    ConnectProducerTransport {
+        #[serde(default)]
+        transport_id: Option<TransportId>,
+        dtls_parameters: DtlsParameters,
+    },
+
+    /// Request to restart ICE of the producer or consumer transport, for instance after network
+    /// change, responded with `IceRestarted`
+    #[serde(rename_all = "camelCase")]
+    RestartIce { transport_id: TransportId },
+
+    /// Request to produce a new audio or video track with specified RTP parameters on given
+    /// producer transport (the first one by default). Source defaults to the camera or the
+    /// microphone depending on the kind.
     #[serde(rename_all = "camelCase")]
     Produce {
+        #[serde(default)]
+        transport_id: Option<TransportId>,
         kind: MediaKind,
         rtp_parameters: RtpParameters,
+        #[serde(default)]
+        source: Option<ProducerSource>,
     },
 
+    /// Request to pause own producer, other participants stop receiving it
+    #[serde(rename_all = "camelCase")]
+    PauseProducer { producer_id: ProducerId },
+
+    /// Request to resume own producer that was previously paused
+    #[serde(rename_all = "camelCase")]
+    ResumeProducer { producer_id: ProducerId },
+
+    /// Request to close own producer, it is removed from the room
+    #[serde(rename_all = "camelCase")]
+    CloseProducer { producer_id: ProducerId },
+
     /// Request to connect consumer transport with client-side DTLS parameters
     #[serde(rename_all = "camelCase")]
     ConnectConsumerTransport { dtls_parameters: DtlsParameters },
@@ -96,25 +465,132 @@ pub enum ClientMessage {
     #[serde(rename_all = "camelCase")]
     Consume { producer_id: ProducerId },
 
+    /// Request to create data producer (data channel sent to the server) on given producer
+    /// transport (the first one by default), responded with `DataProduced`
+    #[serde(rename_all = "camelCase")]
+    ProduceData {
+        #[serde(default)]
+        transport_id: Option<TransportId>,
+        sctp_stream_parameters: SctpStreamParameters,
+        #[serde(default)]
+        label: String,
+        #[serde(default)]
+        protocol: String,
+    },
+
+    /// Request to consume specified data producer of another participant, responded with
+    /// `DataConsumed`
+    #[serde(rename_all = "camelCase")]
+    ConsumeData { data_producer_id: DataProducerId },
+
+    /// Request to close own data producer, it is removed from the room
+    #[serde(rename_all = "camelCase")]
+    CloseDataProducer { data_producer_id: DataProducerId },
+
+    /// Request to close data consumer that is not needed anymore
+    #[serde(rename_all = "camelCase")]
+    CloseDataConsumer { data_consumer_id: DataConsumerId },
+
     /// Request to resume consumer that was previously created
     #[serde(rename_all = "camelCase")]
     ConsumerResume { id: ConsumerId },
 
+    /// Request to pause consumer, for instance when the video is not visible, it can be resumed
+    /// again with `ConsumerResume`
     #[serde(rename_all = "camelCase")]
-    StartRecording { output_name: String },
+    ConsumerPause { id: ConsumerId },
 
+    /// Request to close consumer that is not needed anymore
     #[serde(rename_all = "camelCase")]
-    StopRecording {},
+    ConsumerClose { id: ConsumerId },
+
+    /// Request to limit the simulcast/SVC layers forwarded to the consumer, the highest layers
+    /// are forwarded when `temporal_layer` is not specified
+    #[serde(rename_all = "camelCase")]
+    SetPreferredLayers {
+        consumer_id: ConsumerId,
+        spatial_layer: u8,
+        #[serde(default)]
+        temporal_layer: Option<u8>,
+    },
+
+    /// Request to get stats of own transports, producers and consumers, responded with `Stats`
+    GetStats,
+
+    /// Request to start recording of own audio and video (or of the whole room), `output_name`
+    /// is used as recording ID if specified, otherwise the server generates one
+    #[serde(rename_all = "camelCase")]
+    StartRecording {
+        #[serde(default)]
+        output_name: Option<String>,
+        #[serde(default)]
+        mode: RecordingMode,
+    },
+
+    /// Request to stop recording of own audio and video (or of the whole room)
+    #[serde(rename_all = "camelCase")]
+    StopRecording {
+        #[serde(default)]
+        mode: RecordingMode,
+    },
+}
+
+impl ClientMessage {
+    /// Name of the action, the same as the `action` field in JSON
+    pub fn action(&self) -> &'static str {
+        match self {
+            Self::Init { .. } => "Init",
+            Self::CreateTransport { .. } => "CreateTransport",
+            Self::ConnectProducerTransport { .. } => "ConnectProducerTransport",
+            Self::RestartIce { .. } => "RestartIce",
+            Self::Produce { .. } => "Produce",
+            Self::PauseProducer { .. } => "PauseProducer",
+            Self::ResumeProducer { .. } => "ResumeProducer",
+            Self::CloseProducer { .. } => "CloseProducer",
+            Self::ConnectConsumerTransport { .. } => "ConnectConsumerTransport",
+            Self::Consume { .. } => "Consume",
+            Self::ProduceData { .. } => "ProduceData",
+            Self::ConsumeData { .. } => "ConsumeData",
+            Self::CloseDataProducer { .. } => "CloseDataProducer",
+            Self::CloseDataConsumer { .. } => "CloseDataConsumer",
+            Self::ConsumerResume { .. } => "ConsumerResume",
+            Self::ConsumerPause { .. } => "ConsumerPause",
+            Self::ConsumerClose { .. } => "ConsumerClose",
+            Self::SetPreferredLayers { .. } => "SetPreferredLayers",
+            Self::GetStats => "GetStats",
+            Self::StartRecording { .. } => "StartRecording",
+            Self::StopRecording { .. } => "StopRecording",
+        }
+    }
 }
 
 /// Internal actor messages for convenience
 #[derive(Message)]
 #[rtype(result = "()")]
 pub enum InternalMessage {
+    /// Save newly created transport, response is sent once it is saved since transport with the
+    /// same direction may have been created by a concurrent request meanwhile
+    SaveTransport {
+        request_id: Option<RequestId>,
+        direction: TransportDirection,
+        transport: WebRtcTransport,
+    },
     /// Save producer in connection-specific hashmap to prevent it from being destroyed
     SaveProducer(Producer),
     /// Save consumer in connection-specific hashmap to prevent it from being destroyed
     SaveConsumer(Consumer),
+    /// Remember the latest score of own producer and notify client about it later
+    ProducerScore(ProducerId, Vec<ProducerScore>),
+    /// Remember the latest score of own consumer and notify client about it later
+    ConsumerScore(ConsumerId, ConsumerScore),
+    /// Forget consumer that was closed because its producer or transport was closed
+    RemoveConsumer(ConsumerId),
+    /// Save data producer to prevent it from being destroyed
+    SaveDataProducer(DataProducer),
+    /// Save data consumer to prevent it from being destroyed
+    SaveDataConsumer(DataConsumer),
+    /// Forget data consumer that was closed because its data producer or transport was closed
+    RemoveDataConsumer(DataConsumerId),
     /// Stop/close the WebSocket connection
     Stop,
 }
@@ -0,0 +1,128 @@
+use crate::participant::ParticipantConnection;
+use crate::room::RoomId;
+use actix::clock::{self, Instant};
+use actix::prelude::*;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Secret issued to the client in `Init`, it is used to reattach to the same participant after
+/// WebSocket connection was lost
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
+pub struct SessionToken(Uuid);
+
+impl fmt::Display for SessionToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl SessionToken {
+    pub fn new() -> Self {
+        Self(Uuid::new_v4())
+    }
+}
+
+/// Address of the connection actor that currently serves the session.
+///
+/// Event handlers of consumers and producers outlive a single WebSocket connection, so they send
+/// messages through this instead of a fixed actor address. Messages sent while the session is
+/// suspended are dropped.
+#[derive(Clone, Default)]
+pub struct SessionAddress {
+    address: Arc<Mutex<Option<Addr<ParticipantConnection>>>>,
+}
+
+impl SessionAddress {
+    pub fn set(&self, address: Option<Addr<ParticipantConnection>>) {
+        *self.address.lock() = address;
+    }
+
+    pub fn do_send<M>(&self, message: M)
+    where
+        M: Message + Send + 'static,
+        M::Result: Send,
+        ParticipantConnection: Handler<M>,
+    {
+        if let Some(address) = self.address.lock().as_ref() {
+            address.do_send(message);
+        }
+    }
+}
+
+struct SuspendedSession {
+    connection: ParticipantConnection,
+    deadline: Instant,
+}
+
+/// Sessions whose WebSocket connection was lost, participant stays in the room until either the
+/// client reconnects or the grace period ends
+#[derive(Clone, Default)]
+pub struct SessionStore {
+    sessions: Arc<Mutex<HashMap<SessionToken, SuspendedSession>>>,
+}
+
+impl SessionStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keep connection around for `grace_period`, participant leaves the room if it is not
+    /// resumed by then
+    pub fn suspend(
+        &self,
+        token: SessionToken,
+        connection: ParticipantConnection,
+        grace_period: Duration,
+    ) {
+        let deadline = Instant::now() + grace_period;
+        self.sessions.lock().insert(
+            token,
+            SuspendedSession {
+                connection,
+                deadline,
+            },
+        );
+
+        let sessions = self.clone();
+        actix::spawn(async move {
+            clock::sleep_until(deadline).await;
+            sessions.expire(&token);
+        });
+    }
+
+    /// Take suspended session back, it is only resumed in the room it belongs to
+    pub fn resume(
+        &self,
+        token: &SessionToken,
+        room_id: Option<RoomId>,
+    ) -> Option<ParticipantConnection> {
+        let mut sessions = self.sessions.lock();
+        let session = sessions.get(token)?;
+        if room_id.is_some_and(|room_id| room_id != session.connection.room.id()) {
+            return None;
+        }
+
+        sessions.remove(token).map(|session| session.connection)
+    }
+
+    fn expire(&self, token: &SessionToken) {
+        // Session may have been resumed and suspended again with a later deadline meanwhile
+        let expired = {
+            let mut sessions = self.sessions.lock();
+            match sessions.get(token) {
+                Some(session) if session.deadline <= Instant::now() => sessions.remove(token),
+                _ => None,
+            }
+        };
+
+        // Dropping the connection outside of the lock removes participant from the room
+        if let Some(session) = expired {
+            println!("[participant_id {}] Session expired", session.connection.id);
+        }
+    }
+}
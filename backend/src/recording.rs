@@ -1,23 +1,133 @@
-use std::io::BufRead;
-use std::io::BufReader;
-use std::io::Write;
+use std::collections::HashSet;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{ErrorKind, Write};
 use std::net::IpAddr;
 use std::net::Ipv4Addr;
-use std::process::Child;
-use std::process::Stdio;
-use std::sync::Mutex;
-use std::thread;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use crate::room::media_codecs;
+use crate::config::RecordingConfig;
+use event_listener_primitives::{Bag, HandlerId};
 use mediasoup::plain_transport::*;
 use mediasoup::prelude::*;
 use mediasoup::rtp_parameters::RtpCodecCapabilityFinalized;
+use parking_lot::Mutex;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+pub mod backend;
+pub mod composite;
+pub mod port_allocator;
+pub mod sdp;
+use backend::{Container, ProcessStatus, RecordingBackendKind, RecordingInput, RecordingProcess};
+use port_allocator::{PortAllocator, PortLease};
+use sdp::SdpMedia;
+
+/// Information about finished recording
+#[derive(Debug, Clone)]
+pub struct RecordingSummary {
+    pub recording_id: String,
+    pub path: PathBuf,
+    pub duration: Duration,
+    pub size_bytes: u64,
+}
+
+/// What is being recorded
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub enum RecordingMode {
+    /// Audio and video of a single participant
+    #[default]
+    Participant,
+    /// All participants of the room mixed into a single file
+    Room,
+}
 
-use std::process::Command;
+/// Change of the recording state of a participant, broadcast to everyone in the room
+#[derive(Debug, Clone)]
+pub enum RecordingEvent {
+    Started {
+        recording_id: String,
+    },
+    Stopped(RecordingSummary),
+    Failed {
+        recording_id: String,
+        reason: String,
+    },
+}
 
-static RECORDING_PORT_MIN: u16 = 12000;
-static RECORDING_PORT_MAX: u16 = 13000;
-static RECORDING_PORT: Mutex<u16> = Mutex::new(RECORDING_PORT_MIN);
+/// Validate recording name requested by the client or generate a new one if it is not
+/// specified, the result is used as recording ID and as a part of file names
+pub fn recording_id(output_name: Option<&str>) -> Result<String, String> {
+    // Only allow names that can't escape recording directories or clash with temporary files
+    static VALID_NAME: OnceLock<Regex> = OnceLock::new();
+    let valid_name =
+        VALID_NAME.get_or_init(|| Regex::new(r"^[A-Za-z0-9][A-Za-z0-9_-]{0,63}$").unwrap());
+
+    match output_name.map(str::trim).filter(|name| !name.is_empty()) {
+        Some(name) if valid_name.is_match(name) && !name.ends_with("_tmp") => Ok(name.to_owned()),
+        Some(name) => Err(format!(
+            "Invalid recording name {name:?}, only up to 64 letters, digits, `-` and `_` are allowed"
+        )),
+        None => {
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_millis())
+                .unwrap_or_default();
+            Ok(format!("{}_{}", timestamp, Uuid::new_v4().simple()))
+        }
+    }
+}
+
+/// IDs of recordings in progress in all the rooms, so that two recordings never write into the
+/// same files
+#[derive(Debug, Clone, Default)]
+pub struct RecordingIds {
+    ids: Arc<Mutex<HashSet<String>>>,
+}
+
+impl RecordingIds {
+    /// Reserve ID of the recording that is being started, fails if it is in use already
+    pub fn reserve(&self, recording_id: &str) -> Result<(), String> {
+        if !self.ids.lock().insert(recording_id.to_owned()) {
+            return Err(format!("Recording {recording_id} is already in progress"));
+        }
+
+        Ok(())
+    }
+
+    /// Release ID of the recording that was stopped or has failed
+    pub fn release(&self, recording_id: &str) {
+        self.ids.lock().remove(recording_id);
+    }
+}
+
+/// State of the `Recorder`
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub enum RecordingState {
+    /// Recording is not started yet or was stopped
+    #[default]
+    Idle,
+    Recording,
+    /// Recording process has exited on its own while recording
+    Failed {
+        reason: String,
+    },
+}
+
+#[derive(Default)]
+#[allow(clippy::type_complexity)]
+struct Handlers {
+    failed: Bag<Arc<dyn Fn(&String) + Send + Sync>, String>,
+}
+
+impl fmt::Debug for Handlers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Handlers").finish_non_exhaustive()
+    }
+}
 
 #[derive(Default, Debug)]
 pub struct Recorder {
@@ -25,31 +135,39 @@ pub struct Recorder {
     pub video_transport: Option<PlainTransport>,
     pub audio_consumer: Option<Consumer>,
     pub video_consumer: Option<Consumer>,
-    pub process: Option<Child>,
-    pub is_recording: bool,
-    pub filename: String,
-    pub sdp_filename: String,
-    pub port_number: u16,
+    /// Backend that runs the recording process
+    pub backend: RecordingBackendKind,
+    pub process: Option<Box<dyn RecordingProcess>>,
+    pub state: RecordingState,
+    pub recording_id: String,
+    pub started_at: Option<Instant>,
+    /// Directory where finished recordings are stored
+    pub root_dir: PathBuf,
+    /// Directory where SDP files for the recording process are stored
+    pub sdp_dir: PathBuf,
+    pub sdp_path: PathBuf,
+    /// File the recording process writes to, it is renamed to `output_path` once finished
+    pub tmp_path: PathBuf,
+    pub output_path: PathBuf,
+    /// Ports the recording process receives RTP on, released once recording is stopped
+    pub ports: Option<PortLease>,
+    handlers: Arc<Handlers>,
 }
 
 impl Recorder {
     pub async fn new(
         router: &Router,
+        config: &RecordingConfig,
+        port_allocator: &PortAllocator,
         audio_producer: Option<&Producer>,
         video_producer: Option<&Producer>,
     ) -> Result<Self, String> {
         let mut tmp_self = Recorder::default();
 
-        let mut rp_guard = RECORDING_PORT.lock().expect("lock mutex");
-        let port_number = *rp_guard;
-        *rp_guard = if port_number >= RECORDING_PORT_MAX {
-            RECORDING_PORT_MIN
-        } else {
-            port_number + 4
-        };
-        std::mem::drop(rp_guard);
-
-        tmp_self.port_number = port_number;
+        let ports = port_allocator.lease()?;
+        tmp_self.root_dir = config.root_dir.clone();
+        tmp_self.sdp_dir = config.sdp_dir.clone();
+        tmp_self.backend = config.backend;
 
         // audio
         if let Some(ap) = audio_producer {
@@ -67,8 +185,8 @@ impl Recorder {
 
             let remote_params = PlainTransportRemoteParameters {
                 ip: Some(IpAddr::V4(Ipv4Addr::LOCALHOST)),
-                port: Some(port_number),
-                rtcp_port: Some(port_number + 1),
+                port: Some(ports.audio_rtp_port()),
+                rtcp_port: Some(ports.audio_rtcp_port()),
                 srtp_parameters: None,
             };
 
@@ -80,15 +198,8 @@ impl Recorder {
             log::debug!("audio transport tuple: {:?}", &transport.tuple());
             log::debug!("audio transport rtcp tuple: {:?}", &transport.rtcp_tuple());
 
-            let src_cap = convert_rtp_capabilities(router.rtp_capabilities());
-            log::debug!("audio capabilities: {:?}", &src_cap);
-
-            let mut cap = RtpCapabilities::default();
-
-            cap.header_extensions = src_cap.header_extensions.clone();
-
-            let codec = media_codecs()[0].clone();
-            cap.codecs.push(codec);
+            let cap = consumer_capabilities(router, MediaKind::Audio);
+            log::debug!("audio capabilities: {:?}", &cap);
 
             let mut consume_options = ConsumerOptions::new(ap.id(), cap);
             consume_options.paused = true;
@@ -118,8 +229,8 @@ impl Recorder {
 
             let remote_params = PlainTransportRemoteParameters {
                 ip: Some(IpAddr::V4(Ipv4Addr::LOCALHOST)),
-                port: Some(port_number + 2),
-                rtcp_port: Some(port_number + 3),
+                port: Some(ports.video_rtp_port()),
+                rtcp_port: Some(ports.video_rtcp_port()),
                 srtp_parameters: None,
             };
 
@@ -131,15 +242,8 @@ impl Recorder {
             log::debug!("video transport tuple: {:?}", &transport.tuple());
             log::debug!("video transport rtcp tuple: {:?}", &transport.rtcp_tuple());
 
-            let src_cap = convert_rtp_capabilities(router.rtp_capabilities());
-            log::debug!("video capabilities: {:?}", &src_cap);
-
-            let mut cap = RtpCapabilities::default();
-
-            cap.header_extensions = src_cap.header_extensions.clone();
-
-            let codec = media_codecs()[1].clone();
-            cap.codecs.push(codec);
+            let cap = consumer_capabilities(router, MediaKind::Video);
+            log::debug!("video capabilities: {:?}", &cap);
 
             let mut consume_options = ConsumerOptions::new(vp.id(), cap);
             consume_options.paused = true;
@@ -153,144 +257,185 @@ impl Recorder {
             tmp_self.video_consumer = Some(consumer);
         }
 
+        tmp_self.ports = Some(ports);
+
         Ok(tmp_self)
     }
 
-    pub async fn start_recording(&mut self, output_name: &str) -> Result<(), String> {
-        self.start_recording_process(output_name).await?;
+    /// Start recording with the ID returned by `recording_id()`
+    pub async fn start_recording(&mut self, recording_id: &str) -> Result<(), String> {
+        self.start_recording_process(recording_id).await?;
+        self.resume_consumers().await?;
+        // Recording can only start decoding video from a key frame
+        self.request_key_frame().await;
 
-        if let Some(c) = self.audio_consumer.as_ref() {
-            c.resume()
-                .await
-                .map_err(|e| format!("Failed to start audio consumer: {e}"))?;
+        self.state = RecordingState::Recording;
+        self.recording_id = recording_id.to_string();
+        self.started_at = Some(Instant::now());
 
-            log::debug!("redume audio consumer");
-        }
+        Ok(())
+    }
 
-        if let Some(c) = self.video_consumer.as_ref() {
-            c.resume()
-                .await
-                .map_err(|e| format!("Failed to start video consumer: {e}"))?;
+    /// Whether recording is in progress
+    pub fn is_recording(&self) -> bool {
+        self.state == RecordingState::Recording
+    }
 
-            log::debug!("redume video consumer");
+    /// Exit status of the recording process if it has exited while recording, `on_failed` is
+    /// called for it too, but only once the process output ends
+    pub fn exited_process_status(&self) -> Option<String> {
+        if !self.is_recording() {
+            return None;
         }
 
-        self.is_recording = true;
-        self.filename = output_name.to_string();
+        match self.process.as_ref()?.status() {
+            ProcessStatus::Running => None,
+            ProcessStatus::Exited(status) => Some(status),
+        }
+    }
 
-        Ok(())
+    /// Subscribe to notification when the recording process exits on its own while recording,
+    /// the reason contains exit status and last lines of the process output
+    pub fn on_failed<F: Fn(&String) + Send + Sync + 'static>(&self, callback: F) -> HandlerId {
+        self.handlers.failed.add(Arc::new(callback))
     }
 
-    fn create_sdp_file(&mut self, sdp_filename: &str, port_number: u16) -> Result<(), String> {
-        let text = format!(
-            r#"
-v=0
-o=- 0 0 IN IP4 127.0.0.1
-s=-
-c=IN IP4 127.0.0.1
-t=0 0
-m=audio {} RTP/AVPF 111
-a=rtcp:{}
-a=rtpmap:111 opus/48000/2
-a=fmtp:111 minptime=10;useinbandfec=1
-m=video {} RTP/AVPF 125
-a=rtcp:{}
-a=rtpmap:125 H264/90000
-"#,
-            port_number,
-            port_number + 1,
-            port_number + 2,
-            port_number + 3
-        );
+    /// Move recorder to the failed state after its recording process has exited on its own,
+    /// recorded data received so far is left in the temporary file
+    pub async fn fail(&mut self, reason: String) {
+        self.process = None;
+        let _ = std::fs::remove_file(&self.sdp_path);
 
-        let _ = std::fs::write(sdp_filename, text);
+        if let Err(error) = self.pause_consumers().await {
+            log::warn!("{error}");
+        }
+        self.ports = None;
 
-        Ok(())
+        self.state = RecordingState::Failed { reason };
     }
 
-    async fn start_recording_process(&mut self, output_name: &str) -> Result<(), String> {
-        let sdp_filename = format!("./profiles/{}.sdp", output_name);
-
-        self.create_sdp_file(&sdp_filename, self.port_number)?;
-        let cmd_program = "ffmpeg";
-
-        let output_path = format!("./recordings/{}_tmp.mp4", output_name);
-
-        let video_format = vec!["-f", "mp4", "-strict", "experimental"];
-
-        // Run process
-        let cmd_args = [
-            vec![
-                "-protocol_whitelist",
-                "file,rtp,udp",
-                "-probesize",
-                "50M",
-                "-fflags",
-                "+genpts",
-                "-i",
-                &sdp_filename,
-            ],
-            video_format,
-            vec!["-y", output_path.as_ref()],
-        ]
-        .concat();
-
-        log::info!("spawn ffmpeg: {:?}", &cmd_program);
-
-        let mut proc = Command::new(cmd_program)
-            .args(cmd_args)
-            .stderr(Stdio::piped())
-            .stdin(Stdio::piped())
-            .spawn()
-            .map_err(|error| format!("Failed to consume audio transport: {error}"))?;
-
-        log::info!("get ffmpeg handle");
-
-        let stderr = proc
-            .stderr
-            .take()
-            .ok_or("Failed to take stdout".to_owned())?;
-        log::info!("take stderr");
-
-        let mut r = BufReader::with_capacity(10000000, stderr);
-
-        log::info!("get buf reader");
-
-        loop {
-            log::info!("get ffmpeg output");
-            let mut line = String::new();
-            let result = r.read_line(&mut line);
-            if let Err(e) = result {
-                return Err(format!("Failed to read line: {e}"));
-            }
+    /// Write SDP describing the media sent to the recording ports with the codecs consumers
+    /// have negotiated, media the participant doesn't produce is left out so that the recording
+    /// process doesn't wait for it. Existing file is never overwritten.
+    pub fn write_sdp(&self, sdp_path: &Path) -> Result<(), String> {
+        let ports = self
+            .ports
+            .as_ref()
+            .ok_or_else(|| "Recording ports were already released".to_owned())?;
 
-            if let Ok(0) = result {
-                return Err("FFmpeg is quit".to_owned());
-            }
+        let mut media = Vec::with_capacity(2);
+        if let Some(c) = self.audio_consumer.as_ref() {
+            media.push(SdpMedia {
+                kind: MediaKind::Audio,
+                rtp_port: ports.audio_rtp_port(),
+                rtcp_port: ports.audio_rtcp_port(),
+                rtp_parameters: c.rtp_parameters(),
+            });
+        }
+        if let Some(c) = self.video_consumer.as_ref() {
+            media.push(SdpMedia {
+                kind: MediaKind::Video,
+                rtp_port: ports.video_rtp_port(),
+                rtcp_port: ports.video_rtcp_port(),
+                rtp_parameters: c.rtp_parameters(),
+            });
+        }
+        let text = sdp::build(&media)?;
+
+        // Creating the file fails if it exists, unlike checking for it first
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(sdp_path)
+            .and_then(|mut file| file.write_all(text.as_bytes()))
+            .map_err(|error| match error.kind() {
+                ErrorKind::AlreadyExists => format!("{} already exists", sdp_path.display()),
+                _ => format!("Failed to write {}: {error}", sdp_path.display()),
+            })
+    }
 
-            log::debug!("line: {}", &line);
-            if line.starts_with("ffmpeg version") {
-                break;
+    /// Ask the sender of the video to send a key frame, so that recording can be decoded from
+    /// the start
+    pub async fn request_key_frame(&self) {
+        if let Some(c) = self.video_consumer.as_ref() {
+            if let Err(error) = c.request_key_frame().await {
+                log::warn!("Failed to request key frame: {error}");
             }
         }
+    }
 
-        thread::spawn(move || {
-            log::info!("read lines.");
-            loop {
-                let mut buf = String::new();
-                let result = r.read_line(&mut buf);
-                if result.is_err() || result.unwrap_or(1) == 0 {
-                    break;
-                }
+    /// Temporary and final paths of the recording file
+    fn recording_paths(&self, recording_id: &str, container: Container) -> (PathBuf, PathBuf) {
+        let extension = container.extension();
+        (
+            self.root_dir
+                .join(format!("{recording_id}_tmp.{extension}")),
+            self.root_dir.join(format!("{recording_id}.{extension}")),
+        )
+    }
 
-                log::info!("{}", &buf);
-            }
+    async fn start_recording_process(&mut self, recording_id: &str) -> Result<(), String> {
+        for dir in [&self.root_dir, &self.sdp_dir] {
+            std::fs::create_dir_all(dir)
+                .map_err(|error| format!("Failed to create {}: {error}", dir.display()))?;
+        }
+
+        let video = self.video_consumer.as_ref().map(Consumer::rtp_parameters);
+        let container = Container::for_video(video)?;
+
+        let sdp_path = self.sdp_dir.join(format!("{recording_id}.sdp"));
+        let (tmp_path, output_path) = self.recording_paths(recording_id, container);
+
+        // Never overwrite existing recordings (or the files of a recording in progress), in any
+        // container so that recording IDs stay unique, SDP file is checked when it is created
+        let exists = Container::ALL.iter().any(|&container| {
+            let (tmp_path, output_path) = self.recording_paths(recording_id, container);
+            tmp_path.exists() || output_path.exists()
         });
+        if exists {
+            return Err(format!("Recording {recording_id} already exists"));
+        }
 
-        log::debug!("ffmpeg has been started.");
+        self.write_sdp(&sdp_path)?;
+
+        let input = RecordingInput {
+            sdp_path: &sdp_path,
+            ports: self
+                .ports
+                .as_ref()
+                .ok_or_else(|| "Recording ports were already released".to_owned())?,
+            audio: self.audio_consumer.as_ref().map(Consumer::rtp_parameters),
+            video,
+            output_path: &tmp_path,
+            container,
+        };
+        let process = self.backend.backend().start(
+            &input,
+            Box::new({
+                let handlers = Arc::clone(&self.handlers);
 
-        self.process = Some(proc);
-        self.sdp_filename = sdp_filename;
+                move |reason| {
+                    handlers.failed.call_simple(&reason);
+                }
+            }),
+        );
+        let process = match process {
+            Ok(process) => process,
+            Err(error) => {
+                let _ = std::fs::remove_file(&sdp_path);
+                return Err(error);
+            }
+        };
+
+        log::debug!(
+            "recording process has been started with {:?}.",
+            self.backend
+        );
+
+        self.process = Some(process);
+        self.sdp_path = sdp_path;
+        self.tmp_path = tmp_path;
+        self.output_path = output_path;
         Ok(())
     }
 
@@ -298,45 +443,86 @@ a=rtpmap:125 H264/90000
         let proc = std::mem::replace(&mut self.process, None);
 
         log::info!("thread started.");
-        let Some(mut c) = proc else {
+        let Some(mut proc) = proc else {
             return Err("proc is none".to_owned());
         };
 
-        if let Some(stream) = c.stdin.as_mut() {
-            stream.write(b"q\n");
-            stream.flush();
+        proc.stop()
+    }
+
+    pub async fn stop_recording(&mut self) -> Result<RecordingSummary, String> {
+        if !self.is_recording() {
+            return Err("Recording is not in progress".to_owned());
         }
+        self.state = RecordingState::Idle;
 
-        let _ = c
-            .wait()
-            .map_err(|e| format!("FFmpeg failed to exit: {e}"))?;
+        if let Err(error) = self.stop_recording_process() {
+            log::warn!("Failed to stop recording process: {error}");
+        }
 
-        Ok(())
+        // Unlike `rename`, linking fails instead of replacing a file that appeared meanwhile
+        let renamed = std::fs::hard_link(&self.tmp_path, &self.output_path)
+            .and_then(|()| std::fs::remove_file(&self.tmp_path));
+        let _ = std::fs::remove_file(&self.sdp_path);
+
+        // Ports are released only after consumers are paused, so that nothing is sent to them
+        // anymore when they are leased by another recording
+        let result = self.pause_consumers().await;
+        self.ports = None;
+        result?;
+
+        renamed.map_err(|error| {
+            format!(
+                "Failed to move recording to {}: {error}",
+                self.output_path.display()
+            )
+        })?;
+
+        Ok(RecordingSummary {
+            recording_id: self.recording_id.clone(),
+            path: self.output_path.clone(),
+            duration: self
+                .started_at
+                .map(|started_at| started_at.elapsed())
+                .unwrap_or_default(),
+            size_bytes: std::fs::metadata(&self.output_path)
+                .map(|metadata| metadata.len())
+                .unwrap_or_default(),
+        })
     }
 
-    pub async fn stop_recording(&mut self) -> Result<(), String> {
-        if self.is_recording == false {
-            return Ok(());
+    /// Start sending media to the recording ports
+    pub async fn resume_consumers(&self) -> Result<(), String> {
+        if let Some(c) = self.audio_consumer.as_ref() {
+            c.resume()
+                .await
+                .map_err(|e| format!("Failed to start audio consumer: {e}"))?;
+
+            log::debug!("redume audio consumer");
         }
 
-        self.stop_recording_process();
-        let filename = self.filename.clone();
+        if let Some(c) = self.video_consumer.as_ref() {
+            c.resume()
+                .await
+                .map_err(|e| format!("Failed to start video consumer: {e}"))?;
 
-        let src_path = format!("./recordings/{}_tmp.mp4", &filename);
-        let dest_path = format!("./recordings/{}.mp4", &filename);
-        let _ = std::fs::rename(src_path, dest_path);
-        let _ = std::fs::remove_file(&self.sdp_filename);
+            log::debug!("redume video consumer");
+        }
+
+        Ok(())
+    }
 
+    async fn pause_consumers(&self) -> Result<(), String> {
         if let Some(c) = self.audio_consumer.as_ref() {
             c.pause()
                 .await
-                .map_err(|e| "Failed to pause audio consumer: {e}".to_owned())?;
+                .map_err(|e| format!("Failed to pause audio consumer: {e}"))?;
         }
 
         if let Some(c) = self.video_consumer.as_ref() {
             c.pause()
                 .await
-                .map_err(|e| "Failed to pause video consumer: {e}".to_owned())?;
+                .map_err(|e| format!("Failed to pause video consumer: {e}"))?;
         }
 
         Ok(())
@@ -377,6 +563,31 @@ fn convert_rtp_codec_capability(src: &RtpCodecCapabilityFinalized) -> RtpCodecCa
     }
 }
 
+/// Capabilities of the recording process, which accepts any codec of the given kind supported by
+/// the router (without retransmission since it can't request it)
+fn consumer_capabilities(router: &Router, kind: MediaKind) -> RtpCapabilities {
+    let mut cap = convert_rtp_capabilities(router.rtp_capabilities());
+
+    cap.codecs.retain(|codec| match codec {
+        RtpCodecCapability::Audio { .. } => kind == MediaKind::Audio,
+        RtpCodecCapability::Video { mime_type, .. } => {
+            kind == MediaKind::Video && *mime_type != MimeTypeVideo::Rtx
+        }
+    });
+
+    // Recording process only sends receiver reports and NACK/PLI/FIR, bandwidth estimation
+    // feedback would never arrive
+    for codec in &mut cap.codecs {
+        let (RtpCodecCapability::Audio { rtcp_feedback, .. }
+        | RtpCodecCapability::Video { rtcp_feedback, .. }) = codec;
+        rtcp_feedback.retain(|feedback| {
+            !matches!(feedback, RtcpFeedback::TransportCc | RtcpFeedback::GoogRemb)
+        });
+    }
+
+    cap
+}
+
 fn convert_rtp_capabilities(src: &RtpCapabilitiesFinalized) -> RtpCapabilities {
     let mut dest = RtpCapabilities::default();
 
@@ -390,3 +601,32 @@ fn convert_rtp_capabilities(src: &RtpCapabilitiesFinalized) -> RtpCapabilities {
 
     dest
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recording_id_accepts_valid_names() {
+        assert_eq!(recording_id(Some("meeting-1_a")).unwrap(), "meeting-1_a");
+        assert_eq!(recording_id(Some("  trimmed ")).unwrap(), "trimmed");
+        assert_eq!(recording_id(Some(&"a".repeat(64))).unwrap(), "a".repeat(64));
+    }
+
+    #[test]
+    fn recording_id_rejects_invalid_names() {
+        for name in ["../x", "a/b", "x_tmp", "-x", "a.b", &"a".repeat(65)] {
+            assert!(recording_id(Some(name)).is_err(), "{name:?} was accepted");
+        }
+    }
+
+    #[test]
+    fn recording_id_is_generated_when_missing() {
+        for name in [None, Some(""), Some("   ")] {
+            let id = recording_id(name).unwrap();
+            assert!(!id.is_empty());
+            assert_eq!(recording_id(Some(&id)).unwrap(), id);
+        }
+        assert_ne!(recording_id(None).unwrap(), recording_id(None).unwrap());
+    }
+}
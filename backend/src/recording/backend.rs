@@ -0,0 +1,472 @@
+use super::port_allocator::PortLease;
+use super::sdp::{self, SdpCodec};
+use mediasoup::prelude::*;
+use parking_lot::Mutex;
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+/// Number of last lines of the recording process output reported when it fails
+const STDERR_TAIL_LINES: usize = 10;
+
+/// Media of a single participant sent to the recording process
+#[derive(Debug)]
+pub struct RecordingInput<'a> {
+    /// SDP describing the media sent to `ports`
+    pub sdp_path: &'a Path,
+    pub ports: &'a PortLease,
+    /// RTP parameters of the audio consumer, if audio is recorded
+    pub audio: Option<&'a RtpParameters>,
+    /// RTP parameters of the video consumer, if video is recorded
+    pub video: Option<&'a RtpParameters>,
+    /// File the recording process writes to
+    pub output_path: &'a Path,
+    /// Format of `output_path`
+    pub container: Container,
+}
+
+/// File format of a recording, chosen so that the recorded codecs can be stored as they are
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum Container {
+    #[default]
+    Mp4,
+    Webm,
+}
+
+impl Container {
+    pub const ALL: [Self; 2] = [Self::Mp4, Self::Webm];
+
+    /// Container for the video codec: WebM for VP8 and VP9, MP4 for H264 and audio only
+    /// recordings
+    pub fn for_video(video: Option<&RtpParameters>) -> Result<Self, String> {
+        let rtp_parameters = match video {
+            Some(rtp_parameters) => rtp_parameters,
+            None => return Ok(Self::Mp4),
+        };
+
+        let codec = media_codec("video", rtp_parameters)?;
+        match codec.encoding_name.to_ascii_lowercase().as_str() {
+            "h264" => Ok(Self::Mp4),
+            "vp8" | "vp9" => Ok(Self::Webm),
+            _ => Err(format!("{} video can't be recorded", codec.encoding_name)),
+        }
+    }
+
+    /// Extension of the recording files
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Mp4 => "mp4",
+            Self::Webm => "webm",
+        }
+    }
+
+    /// Name of the `ffmpeg` muxer
+    fn ffmpeg_format(self) -> &'static str {
+        match self {
+            Self::Mp4 => "mp4",
+            Self::Webm => "webm",
+        }
+    }
+
+    /// Name of the GStreamer muxer element
+    fn gstreamer_muxer(self) -> &'static str {
+        match self {
+            Self::Mp4 => "mp4mux",
+            Self::Webm => "webmmux",
+        }
+    }
+}
+
+/// Status of the recording process
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ProcessStatus {
+    Running,
+    /// Process has exited, with its exit status
+    Exited(String),
+}
+
+/// External program that receives RTP from the recorder and writes it into a file
+pub trait RecordingBackend: fmt::Debug + Send + Sync {
+    /// Start recording process, `on_unexpected_exit` is called with exit status and last lines
+    /// of the process output if the process exits before it is stopped
+    fn start(
+        &self,
+        input: &RecordingInput<'_>,
+        on_unexpected_exit: Box<dyn FnOnce(String) + Send>,
+    ) -> Result<Box<dyn RecordingProcess>, String>;
+}
+
+/// Running recording process started by `RecordingBackend`
+pub trait RecordingProcess: fmt::Debug + Send {
+    /// Ask the process to finish writing its output and wait for it to exit
+    fn stop(&mut self) -> Result<(), String>;
+
+    /// Whether the process is still running
+    fn status(&self) -> ProcessStatus;
+}
+
+/// Recording backend selected in the config
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum RecordingBackendKind {
+    #[default]
+    Ffmpeg,
+    Gstreamer,
+}
+
+impl RecordingBackendKind {
+    /// Get the backend implementation
+    pub fn backend(self) -> &'static dyn RecordingBackend {
+        match self {
+            Self::Ffmpeg => &FfmpegBackend,
+            Self::Gstreamer => &GstreamerBackend,
+        }
+    }
+}
+
+impl FromStr for RecordingBackendKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "ffmpeg" => Ok(Self::Ffmpeg),
+            "gstreamer" | "gst" => Ok(Self::Gstreamer),
+            _ => Err(format!("Unknown recording backend: {s}")),
+        }
+    }
+}
+
+impl TryFrom<String> for RecordingBackendKind {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+/// Records with `ffmpeg`, reading RTP as described by the SDP file
+#[derive(Debug)]
+pub struct FfmpegBackend;
+
+impl RecordingBackend for FfmpegBackend {
+    fn start(
+        &self,
+        input: &RecordingInput<'_>,
+        on_unexpected_exit: Box<dyn FnOnce(String) + Send>,
+    ) -> Result<Box<dyn RecordingProcess>, String> {
+        let sdp_arg = input.sdp_path.to_string_lossy();
+        let output_arg = input.output_path.to_string_lossy();
+
+        let cmd_args = [
+            "-protocol_whitelist",
+            "file,rtp,udp",
+            "-probesize",
+            "50M",
+            "-fflags",
+            "+genpts",
+            "-i",
+            &*sdp_arg,
+            "-f",
+            input.container.ffmpeg_format(),
+            "-strict",
+            "experimental",
+            // Fail instead of overwriting in case the file appeared after it was checked
+            "-n",
+            &*output_arg,
+        ];
+
+        let process = ChildProcess::spawn_ffmpeg(&cmd_args, on_unexpected_exit)?;
+
+        Ok(Box::new(process))
+    }
+}
+
+/// Records with `gst-launch-1.0`, receiving RTP on the ports directly since GStreamer doesn't
+/// need SDP (RTCP is not used)
+#[derive(Debug)]
+pub struct GstreamerBackend;
+
+impl RecordingBackend for GstreamerBackend {
+    fn start(
+        &self,
+        input: &RecordingInput<'_>,
+        on_unexpected_exit: Box<dyn FnOnce(String) + Send>,
+    ) -> Result<Box<dyn RecordingProcess>, String> {
+        // Never overwrite existing files, `filesink` would truncate them
+        if input.output_path.exists() {
+            return Err(format!("{} already exists", input.output_path.display()));
+        }
+
+        // `-e` makes the pipeline finish the file on interrupt instead of leaving it broken
+        let mut pipeline = vec!["-e".to_owned()];
+        if let Some(rtp_parameters) = input.audio {
+            pipeline.push(gstreamer_source(
+                "audio",
+                input.ports.audio_rtp_port(),
+                rtp_parameters,
+            )?);
+        }
+        if let Some(rtp_parameters) = input.video {
+            pipeline.push(gstreamer_source(
+                "video",
+                input.ports.video_rtp_port(),
+                rtp_parameters,
+            )?);
+        }
+        pipeline.push(format!(
+            "{} name=mux ! filesink",
+            input.container.gstreamer_muxer()
+        ));
+
+        // `gst-launch-1.0` joins its arguments into a single pipeline description, escaping
+        // spaces within each of them, so the path is passed as a separate argument unquoted
+        let location = format!("location={}", input.output_path.display());
+        let cmd_args = pipeline
+            .iter()
+            .flat_map(|part| part.split(' '))
+            .chain([location.as_str()])
+            .collect::<Vec<_>>();
+
+        let process = ChildProcess::spawn(
+            "gst-launch-1.0",
+            &cmd_args,
+            None,
+            StopSignal::Interrupt,
+            on_unexpected_exit,
+        )?;
+
+        Ok(Box::new(process))
+    }
+}
+
+/// Part of the pipeline receiving RTP stream on the port and feeding it into `mux`
+fn gstreamer_source(
+    media: &str,
+    port: u16,
+    rtp_parameters: &RtpParameters,
+) -> Result<String, String> {
+    let codec = media_codec(media, rtp_parameters)?;
+    let SdpCodec {
+        payload_type,
+        encoding_name,
+        clock_rate,
+        ..
+    } = &codec;
+
+    let depay = match encoding_name.to_ascii_lowercase().as_str() {
+        "opus" => "rtpopusdepay ! opusparse",
+        "h264" => "rtph264depay ! h264parse",
+        "vp8" => "rtpvp8depay",
+        "vp9" => "rtpvp9depay",
+        _ => {
+            return Err(format!(
+                "{encoding_name} is not supported by the GStreamer recording backend"
+            ))
+        }
+    };
+
+    Ok(format!(
+        "udpsrc port={port} caps=application/x-rtp,media={media},clock-rate={clock_rate},\
+         encoding-name={},payload={payload_type} ! rtpjitterbuffer ! {depay} ! queue ! mux.",
+        encoding_name.to_ascii_uppercase()
+    ))
+}
+
+/// Codec the media is actually sent with, skipping retransmission
+fn media_codec(media: &str, rtp_parameters: &RtpParameters) -> Result<SdpCodec, String> {
+    sdp::codecs(rtp_parameters)?
+        .into_iter()
+        .find(|codec| !codec.is_rtx())
+        .ok_or_else(|| format!("No {media} codec to record"))
+}
+
+/// How the recording process is asked to finish writing its output
+#[derive(Debug, Copy, Clone)]
+pub enum StopSignal {
+    /// Write the bytes to the standard input of the process
+    Stdin(&'static [u8]),
+    /// Send `SIGINT` to the process
+    Interrupt,
+}
+
+/// Child process whose output is watched so that unexpected exit is detected
+#[derive(Debug)]
+pub struct ChildProcess {
+    child: Arc<Mutex<Child>>,
+    /// Set when the process is being stopped, so that its exit is not reported as a failure
+    stopping: Arc<AtomicBool>,
+    stop_signal: StopSignal,
+}
+
+impl ChildProcess {
+    /// Spawn `ffmpeg`, which is stopped by typing `q`
+    pub fn spawn_ffmpeg<F>(cmd_args: &[&str], on_unexpected_exit: F) -> Result<Self, String>
+    where
+        F: FnOnce(String) + Send + 'static,
+    {
+        Self::spawn(
+            "ffmpeg",
+            cmd_args,
+            Some("ffmpeg version"),
+            StopSignal::Stdin(b"q\n"),
+            on_unexpected_exit,
+        )
+    }
+
+    /// Spawn recording process, waiting until it prints `ready_line` if specified
+    pub fn spawn<F>(
+        cmd_program: &str,
+        cmd_args: &[&str],
+        ready_line: Option<&str>,
+        stop_signal: StopSignal,
+        on_unexpected_exit: F,
+    ) -> Result<Self, String>
+    where
+        F: FnOnce(String) + Send + 'static,
+    {
+        log::info!("spawn {cmd_program}: {cmd_args:?}");
+
+        let mut proc = Command::new(cmd_program)
+            .args(cmd_args)
+            .stderr(Stdio::piped())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()
+            .map_err(|error| format!("Failed to spawn {cmd_program}: {error}"))?;
+
+        let stderr = proc
+            .stderr
+            .take()
+            .ok_or("Failed to take stderr".to_owned())?;
+
+        let mut r = BufReader::with_capacity(10000000, stderr);
+
+        if let Some(ready_line) = ready_line {
+            loop {
+                let mut line = String::new();
+                let result = r.read_line(&mut line);
+                if let Err(e) = result {
+                    return Err(format!("Failed to read line: {e}"));
+                }
+
+                if let Ok(0) = result {
+                    return Err(format!("{cmd_program} has quit"));
+                }
+
+                log::debug!("line: {}", &line);
+                if line.starts_with(ready_line) {
+                    break;
+                }
+            }
+        }
+
+        let child = Arc::new(Mutex::new(proc));
+        let stopping = Arc::<AtomicBool>::default();
+
+        // Output ends when the process exits, which is a failure unless it is being stopped
+        thread::spawn({
+            let child = Arc::clone(&child);
+            let stopping = Arc::clone(&stopping);
+            let cmd_program = cmd_program.to_owned();
+
+            move || {
+                let mut tail = VecDeque::with_capacity(STDERR_TAIL_LINES);
+                loop {
+                    let mut buf = String::new();
+                    let result = r.read_line(&mut buf);
+                    if result.is_err() || result.unwrap_or(1) == 0 {
+                        break;
+                    }
+
+                    log::info!("{}", &buf);
+                    if tail.len() == STDERR_TAIL_LINES {
+                        tail.pop_front();
+                    }
+                    tail.push_back(buf.trim_end().to_owned());
+                }
+
+                let status = child.lock().wait();
+                if stopping.load(Ordering::SeqCst) {
+                    return;
+                }
+
+                let status = match status {
+                    Ok(status) => status.to_string(),
+                    Err(error) => format!("unknown status ({error})"),
+                };
+                let reason = format!(
+                    "{cmd_program} exited unexpectedly with {status}:\n{}",
+                    Vec::from(tail).join("\n")
+                );
+                log::error!("{reason}");
+
+                on_unexpected_exit(reason);
+            }
+        });
+
+        Ok(Self {
+            child,
+            stopping,
+            stop_signal,
+        })
+    }
+}
+
+impl RecordingProcess for ChildProcess {
+    fn stop(&mut self) -> Result<(), String> {
+        self.stopping.store(true, Ordering::SeqCst);
+        let mut c = self.child.lock();
+
+        match self.stop_signal {
+            StopSignal::Stdin(bytes) => {
+                if let Some(stream) = c.stdin.as_mut() {
+                    let _ = stream.write_all(bytes);
+                    let _ = stream.flush();
+                }
+            }
+            StopSignal::Interrupt => {
+                // PID of the process that was reaped already may belong to another process
+                if let Ok(None) = c.try_wait() {
+                    // SAFETY: `kill` has no memory safety requirements
+                    unsafe {
+                        libc::kill(c.id() as libc::pid_t, libc::SIGINT);
+                    }
+                }
+            }
+        }
+
+        c.wait()
+            .map_err(|e| format!("Recording process failed to exit: {e}"))?;
+
+        Ok(())
+    }
+
+    fn status(&self) -> ProcessStatus {
+        match self.child.lock().try_wait() {
+            Ok(None) => ProcessStatus::Running,
+            Ok(Some(status)) => ProcessStatus::Exited(status.to_string()),
+            Err(error) => ProcessStatus::Exited(format!("unknown status ({error})")),
+        }
+    }
+}
+
+impl Drop for ChildProcess {
+    fn drop(&mut self) {
+        // Recording that was not stopped (e.g. its recorder failed to start or was dropped) must
+        // not keep writing into the file and receiving on leased ports
+        let mut c = self.child.lock();
+        if let Ok(None) = c.try_wait() {
+            self.stopping.store(true, Ordering::SeqCst);
+            log::warn!("Killing recording process {} that was not stopped", c.id());
+            let _ = c.kill();
+            let _ = c.wait();
+        }
+    }
+}
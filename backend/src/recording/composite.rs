@@ -0,0 +1,499 @@
+use super::backend::{ChildProcess, Container, ProcessStatus, RecordingProcess};
+use super::port_allocator::PortAllocator;
+use super::{Recorder, RecordingSummary};
+use crate::config::RecordingConfig;
+use crate::participant::ParticipantId;
+use event_listener_primitives::{Bag, HandlerId};
+use mediasoup::prelude::*;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Size of the composite video, tiles of all participants are fit into it
+const CANVAS_WIDTH: u32 = 1280;
+const CANVAS_HEIGHT: u32 = 720;
+const FRAME_RATE: u32 = 30;
+
+/// First audio and first video producer of a participant, which are what gets recorded
+#[derive(Debug, Clone)]
+pub struct ParticipantMedia {
+    pub audio: Option<Producer>,
+    pub video: Option<Producer>,
+}
+
+impl ParticipantMedia {
+    fn producer_ids(&self) -> (Option<ProducerId>, Option<ProducerId>) {
+        (
+            self.audio.as_ref().map(Producer::id),
+            self.video.as_ref().map(Producer::id),
+        )
+    }
+}
+
+/// Transports and consumers sending media of a single participant to the recording process,
+/// `Recorder` is used without its own recording process
+#[derive(Debug)]
+struct Source {
+    producer_ids: (Option<ProducerId>, Option<ProducerId>),
+    recorder: Recorder,
+}
+
+/// Single run of the recording process, a new segment is started whenever the set of recorded
+/// producers changes since inputs of a running process can't be changed
+#[derive(Debug)]
+struct Segment {
+    process: ChildProcess,
+    path: PathBuf,
+    sdp_paths: Vec<PathBuf>,
+}
+
+#[derive(Default)]
+#[allow(clippy::type_complexity)]
+struct Handlers {
+    failed: Bag<Arc<dyn Fn(&String) + Send + Sync>, String>,
+}
+
+impl fmt::Debug for Handlers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Handlers").finish_non_exhaustive()
+    }
+}
+
+/// Recording of the whole room into a single file, with videos of all participants laid out in
+/// a grid and their audio mixed together.
+///
+/// Every change of participants starts a new segment, segments are concatenated once recording
+/// is stopped.
+#[derive(Debug)]
+pub struct CompositeRecorder {
+    recording_id: String,
+    /// Participant that has started the recording
+    started_by: ParticipantId,
+    started_at: Instant,
+    router: Router,
+    config: RecordingConfig,
+    port_allocator: PortAllocator,
+    sources: BTreeMap<ParticipantId, Source>,
+    segment: Option<Segment>,
+    /// Files of the segments that have finished, in order
+    segments: Vec<PathBuf>,
+    output_path: PathBuf,
+    handlers: Arc<Handlers>,
+}
+
+impl CompositeRecorder {
+    /// Create recorder with the ID returned by `recording_id()`, nothing is recorded until
+    /// `update()` is called with the media of the participants
+    pub fn new(
+        router: &Router,
+        config: &RecordingConfig,
+        port_allocator: &PortAllocator,
+        recording_id: &str,
+        started_by: ParticipantId,
+    ) -> Result<Self, String> {
+        for dir in [&config.root_dir, &config.sdp_dir] {
+            std::fs::create_dir_all(dir)
+                .map_err(|error| format!("Failed to create {}: {error}", dir.display()))?;
+        }
+
+        // Mixed video is encoded with H264, which goes into MP4
+        let output_path = config
+            .root_dir
+            .join(format!("{recording_id}.{}", Container::Mp4.extension()));
+        // Temporary file of a participant recording with the same ID counts too
+        let exists = Container::ALL.iter().any(|container| {
+            let extension = container.extension();
+            [
+                format!("{recording_id}.{extension}"),
+                format!("{recording_id}_tmp.{extension}"),
+            ]
+            .iter()
+            .any(|name| config.root_dir.join(name).exists())
+        });
+        if exists {
+            return Err(format!("Recording {recording_id} already exists"));
+        }
+
+        Ok(Self {
+            recording_id: recording_id.to_owned(),
+            started_by,
+            started_at: Instant::now(),
+            router: router.clone(),
+            config: config.clone(),
+            port_allocator: port_allocator.clone(),
+            sources: BTreeMap::new(),
+            segment: None,
+            segments: Vec::new(),
+            output_path,
+            handlers: Arc::default(),
+        })
+    }
+
+    /// ID of the recording
+    pub fn recording_id(&self) -> &str {
+        &self.recording_id
+    }
+
+    /// Participant that has started the recording
+    pub fn started_by(&self) -> ParticipantId {
+        self.started_by
+    }
+
+    /// Whether the recording process of the current segment has exited
+    pub fn has_exited(&self) -> bool {
+        self.segment
+            .as_ref()
+            .is_some_and(|segment| segment.process.status() != ProcessStatus::Running)
+    }
+
+    /// Subscribe to notification when the recording process of the current segment exits on
+    /// its own
+    pub fn on_failed<F: Fn(&String) + Send + Sync + 'static>(&self, callback: F) -> HandlerId {
+        self.handlers.failed.add(Arc::new(callback))
+    }
+
+    /// Record media of the given participants, starts a new segment if it is different from
+    /// what is recorded right now
+    pub async fn update(
+        &mut self,
+        participants: BTreeMap<ParticipantId, ParticipantMedia>,
+    ) -> Result<(), String> {
+        let participants = participants
+            .into_iter()
+            .filter(|(_, media)| media.audio.is_some() || media.video.is_some())
+            .collect::<BTreeMap<_, _>>();
+
+        let unchanged = participants.len() == self.sources.len()
+            && participants.iter().all(|(participant_id, media)| {
+                self.sources
+                    .get(participant_id)
+                    .is_some_and(|source| source.producer_ids == media.producer_ids())
+            });
+        if unchanged && self.segment.is_some() {
+            return Ok(());
+        }
+
+        self.stop_segment();
+
+        self.sources.retain(|participant_id, source| {
+            participants
+                .get(participant_id)
+                .is_some_and(|media| source.producer_ids == media.producer_ids())
+        });
+        for (participant_id, media) in participants {
+            if self.sources.contains_key(&participant_id) {
+                continue;
+            }
+
+            let recorder = Recorder::new(
+                &self.router,
+                &self.config,
+                &self.port_allocator,
+                media.audio.as_ref(),
+                media.video.as_ref(),
+            )
+            .await?;
+            self.sources.insert(
+                participant_id,
+                Source {
+                    producer_ids: media.producer_ids(),
+                    recorder,
+                },
+            );
+        }
+
+        // Nothing to record until someone produces again
+        if self.sources.is_empty() {
+            return Ok(());
+        }
+
+        self.start_segment()?;
+
+        for source in self.sources.values() {
+            source.recorder.resume_consumers().await?;
+            // New segment can only be decoded starting from a key frame
+            source.recorder.request_key_frame().await;
+        }
+
+        Ok(())
+    }
+
+    /// Stop recording and concatenate all the segments into the final file
+    pub fn stop(mut self) -> Result<RecordingSummary, String> {
+        self.stop_segment();
+        // Transports and consumers are closed and ports are released when dropped
+        self.sources.clear();
+
+        let segments = std::mem::take(&mut self.segments);
+        let result = concat_segments(&segments, &self.output_path);
+        if result.is_ok() {
+            for segment in &segments {
+                let _ = std::fs::remove_file(segment);
+            }
+        }
+        result?;
+
+        Ok(RecordingSummary {
+            recording_id: self.recording_id.clone(),
+            path: self.output_path.clone(),
+            duration: self.started_at.elapsed(),
+            size_bytes: std::fs::metadata(&self.output_path)
+                .map(|metadata| metadata.len())
+                .unwrap_or_default(),
+        })
+    }
+
+    fn start_segment(&mut self) -> Result<(), String> {
+        let index = self.segments.len();
+        let path = self
+            .config
+            .root_dir
+            .join(format!("{}.part{index}.mp4", self.recording_id));
+
+        let mut sdp_paths = Vec::with_capacity(self.sources.len());
+        let mut inputs = Vec::with_capacity(self.sources.len());
+        for (i, source) in self.sources.values().enumerate() {
+            let sdp_path = self
+                .config
+                .sdp_dir
+                .join(format!("{}.part{index}.{i}.sdp", self.recording_id));
+            source.recorder.write_sdp(&sdp_path)?;
+            sdp_paths.push(sdp_path);
+            inputs.push((
+                source.recorder.audio_consumer.is_some(),
+                source.recorder.video_consumer.is_some(),
+            ));
+        }
+
+        let sdp_args = sdp_paths
+            .iter()
+            .map(|path| path.to_string_lossy())
+            .collect::<Vec<_>>();
+        let path_arg = path.to_string_lossy();
+        let filter_graph = filter_graph(&inputs);
+
+        let mut cmd_args = Vec::new();
+        for sdp_arg in &sdp_args {
+            cmd_args.extend([
+                "-protocol_whitelist",
+                "file,rtp,udp",
+                "-probesize",
+                "50M",
+                "-fflags",
+                "+genpts",
+                "-i",
+                &**sdp_arg,
+            ]);
+        }
+        // All segments are encoded the same way, so that they can be concatenated without
+        // re-encoding
+        let frame_rate = FRAME_RATE.to_string();
+        cmd_args.extend([
+            "-filter_complex",
+            filter_graph.as_str(),
+            "-map",
+            "[vout]",
+            "-map",
+            "[aout]",
+            "-c:v",
+            "libx264",
+            "-preset",
+            "veryfast",
+            "-pix_fmt",
+            "yuv420p",
+            "-r",
+            frame_rate.as_str(),
+            "-c:a",
+            "aac",
+            "-ar",
+            "48000",
+            "-ac",
+            "2",
+            "-f",
+            "mp4",
+            "-n",
+            &*path_arg,
+        ]);
+
+        // Mixing is done by ffmpeg filters regardless of the configured recording backend
+        let process = ChildProcess::spawn_ffmpeg(&cmd_args, {
+            let handlers = Arc::clone(&self.handlers);
+
+            move |reason| {
+                handlers.failed.call_simple(&reason);
+            }
+        });
+        let process = match process {
+            Ok(process) => process,
+            Err(error) => {
+                remove_files(&sdp_paths);
+                return Err(error);
+            }
+        };
+
+        log::debug!(
+            "segment {index} of recording {} started with {} participants",
+            self.recording_id,
+            self.sources.len()
+        );
+
+        self.segment = Some(Segment {
+            process,
+            path,
+            sdp_paths,
+        });
+
+        Ok(())
+    }
+
+    fn stop_segment(&mut self) {
+        let Some(mut segment) = self.segment.take() else {
+            return;
+        };
+
+        if let Err(error) = segment.process.stop() {
+            log::warn!("Failed to stop recording process: {error}");
+        }
+        remove_files(&segment.sdp_paths);
+
+        if segment.path.exists() {
+            self.segments.push(segment.path);
+        }
+    }
+}
+
+impl Drop for CompositeRecorder {
+    fn drop(&mut self) {
+        // Recording that was not stopped (failed or room closed), leave segments on disk
+        self.stop_segment();
+    }
+}
+
+/// Build ffmpeg filter graph laying out videos of the inputs in a grid as `[vout]` and mixing
+/// their audio as `[aout]`, `inputs` tell whether each input has audio and video
+fn filter_graph(inputs: &[(bool, bool)]) -> String {
+    let mut filters = Vec::new();
+
+    let videos = inputs
+        .iter()
+        .enumerate()
+        .filter(|(_, (_, has_video))| *has_video)
+        .map(|(i, _)| i)
+        .collect::<Vec<_>>();
+    if videos.is_empty() {
+        filters.push(format!(
+            "color=c=black:s={CANVAS_WIDTH}x{CANVAS_HEIGHT}:r={FRAME_RATE}[vout]"
+        ));
+    } else {
+        let columns = (videos.len() as f64).sqrt().ceil() as u32;
+        let rows = (videos.len() as u32).div_ceil(columns);
+        // Tile sizes must be even for yuv420p
+        let tile_width = CANVAS_WIDTH / columns / 2 * 2;
+        let tile_height = CANVAS_HEIGHT / rows / 2 * 2;
+
+        let mut layout = Vec::with_capacity(videos.len());
+        for (tile, input) in videos.iter().enumerate() {
+            filters.push(format!(
+                "[{input}:v]scale={tile_width}:{tile_height}:force_original_aspect_ratio=decrease,\
+                 pad={tile_width}:{tile_height}:(ow-iw)/2:(oh-ih)/2,setsar=1[v{tile}]"
+            ));
+            let column = tile as u32 % columns;
+            let row = tile as u32 / columns;
+            layout.push(format!("{}_{}", column * tile_width, row * tile_height));
+        }
+
+        let pad = format!("pad={CANVAS_WIDTH}:{CANVAS_HEIGHT}:(ow-iw)/2:(oh-ih)/2");
+        if videos.len() == 1 {
+            filters.push(format!("[v0]{pad}[vout]"));
+        } else {
+            let labels = (0..videos.len())
+                .map(|tile| format!("[v{tile}]"))
+                .collect::<String>();
+            filters.push(format!(
+                "{labels}xstack=inputs={}:layout={}:fill=black,{pad}[vout]",
+                videos.len(),
+                layout.join("|")
+            ));
+        }
+    }
+
+    let audios = inputs
+        .iter()
+        .enumerate()
+        .filter(|(_, (has_audio, _))| *has_audio)
+        .map(|(i, _)| format!("[{i}:a]"))
+        .collect::<Vec<_>>();
+    if audios.is_empty() {
+        filters.push("anullsrc=r=48000:cl=stereo[aout]".to_owned());
+    } else {
+        filters.push(format!(
+            "{}amix=inputs={}:dropout_transition=0[aout]",
+            audios.concat(),
+            audios.len()
+        ));
+    }
+
+    filters.join(";")
+}
+
+/// Join segments into the output file, segments are encoded the same way so that streams are
+/// just copied
+fn concat_segments(segments: &[PathBuf], output_path: &Path) -> Result<(), String> {
+    match segments {
+        [] => Err("Nothing was recorded".to_owned()),
+        // Unlike `rename`, linking fails instead of replacing a file that appeared meanwhile
+        [segment] => std::fs::hard_link(segment, output_path).map_err(|error| {
+            format!(
+                "Failed to move recording to {}: {error}",
+                output_path.display()
+            )
+        }),
+        segments => {
+            let list_path = output_path.with_extension("txt");
+            let list = segments
+                .iter()
+                .map(|segment| {
+                    let path = segment
+                        .canonicalize()
+                        .unwrap_or_else(|_| segment.clone())
+                        .to_string_lossy()
+                        .replace('\'', r"'\''");
+                    format!("file '{path}'\n")
+                })
+                .collect::<String>();
+            std::fs::write(&list_path, list)
+                .map_err(|error| format!("Failed to write {}: {error}", list_path.display()))?;
+
+            let output = Command::new("ffmpeg")
+                .args(["-f", "concat", "-safe", "0", "-i"])
+                .arg(&list_path)
+                .args(["-c", "copy", "-n"])
+                .arg(output_path)
+                .stdin(Stdio::null())
+                .output();
+            let _ = std::fs::remove_file(&list_path);
+
+            let output = output.map_err(|error| format!("Failed to spawn ffmpeg: {error}"))?;
+            if !output.status.success() {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                let tail = stderr.lines().rev().take(5).collect::<Vec<_>>();
+                return Err(format!(
+                    "Failed to concatenate recording segments ({}):\n{}",
+                    output.status,
+                    tail.into_iter().rev().collect::<Vec<_>>().join("\n")
+                ));
+            }
+
+            Ok(())
+        }
+    }
+}
+
+fn remove_files(paths: &[PathBuf]) {
+    for path in paths {
+        let _ = std::fs::remove_file(path);
+    }
+}
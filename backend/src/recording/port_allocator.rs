@@ -0,0 +1,162 @@
+use parking_lot::Mutex;
+use std::collections::HashSet;
+use std::net::{Ipv4Addr, UdpSocket};
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+
+/// Number of ports used by a single recording: RTP and RTCP for audio and video
+const PORTS_PER_LEASE: u32 = 4;
+
+#[derive(Debug)]
+struct Inner {
+    range: RangeInclusive<u16>,
+    /// Index of the block to try first, blocks are handed out round-robin so that ports
+    /// released a moment ago are not reused right away
+    next_block: u32,
+    /// First ports of the blocks that are currently leased
+    leased: HashSet<u16>,
+}
+
+/// Hands out blocks of 4 consecutive ports from the recording port range, ports are given back
+/// when `PortLease` is dropped
+#[derive(Debug, Clone)]
+pub struct PortAllocator {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl PortAllocator {
+    pub fn new(range: RangeInclusive<u16>) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                range,
+                next_block: 0,
+                leased: HashSet::new(),
+            })),
+        }
+    }
+
+    /// Lease block of ports that is neither leased already nor used by another process
+    pub fn lease(&self) -> Result<PortLease, String> {
+        let mut inner = self.inner.lock();
+        // Computed in `u32`, range may end at the very last port
+        let start = u32::from(*inner.range.start());
+        let end = u32::from(*inner.range.end());
+        let num_blocks = (end + 1).saturating_sub(start) / PORTS_PER_LEASE;
+
+        for i in 0..num_blocks {
+            let block = (inner.next_block + i) % num_blocks;
+            let Ok(base) = u16::try_from(start + block * PORTS_PER_LEASE) else {
+                continue;
+            };
+            if inner.leased.contains(&base) || !is_bindable(base) {
+                continue;
+            }
+
+            inner.leased.insert(base);
+            inner.next_block = (block + 1) % num_blocks;
+
+            return Ok(PortLease {
+                base,
+                allocator: self.clone(),
+            });
+        }
+
+        Err(format!(
+            "No free recording ports left in {:?} ({} recordings in progress)",
+            inner.range,
+            inner.leased.len()
+        ))
+    }
+}
+
+/// Check that all the ports of the block can be bound, recording process will bind them
+fn is_bindable(base: u16) -> bool {
+    (u32::from(base)..u32::from(base) + PORTS_PER_LEASE)
+        .filter_map(|port| u16::try_from(port).ok())
+        .all(|port| UdpSocket::bind((Ipv4Addr::UNSPECIFIED, port)).is_ok())
+}
+
+/// Block of 4 consecutive ports leased from `PortAllocator`
+#[derive(Debug)]
+pub struct PortLease {
+    base: u16,
+    allocator: PortAllocator,
+}
+
+impl PortLease {
+    pub fn audio_rtp_port(&self) -> u16 {
+        self.base
+    }
+
+    pub fn audio_rtcp_port(&self) -> u16 {
+        self.base + 1
+    }
+
+    pub fn video_rtp_port(&self) -> u16 {
+        self.base + 2
+    }
+
+    pub fn video_rtcp_port(&self) -> u16 {
+        self.base + 3
+    }
+}
+
+impl Drop for PortLease {
+    fn drop(&mut self) {
+        self.allocator.inner.lock().leased.remove(&self.base);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lease_at_top_of_port_range() {
+        let allocator = PortAllocator::new(65532..=65535);
+
+        let lease = allocator.lease().unwrap();
+        assert_eq!(lease.audio_rtp_port(), 65532);
+        assert_eq!(lease.audio_rtcp_port(), 65533);
+        assert_eq!(lease.video_rtp_port(), 65534);
+        assert_eq!(lease.video_rtcp_port(), 65535);
+    }
+
+    #[test]
+    fn lease_fails_when_range_is_exhausted() {
+        let allocator = PortAllocator::new(47000..=47008);
+
+        let _first = allocator.lease().unwrap();
+        let _second = allocator.lease().unwrap();
+        // Remaining port is not enough for a whole block
+        assert!(allocator.lease().is_err());
+    }
+
+    #[test]
+    fn lease_hands_out_blocks_round_robin() {
+        let allocator = PortAllocator::new(47100..=47111);
+
+        let first = allocator.lease().unwrap();
+        assert_eq!(first.audio_rtp_port(), 47100);
+        drop(first);
+
+        // Block released a moment ago is not reused right away
+        let second = allocator.lease().unwrap();
+        assert_eq!(second.audio_rtp_port(), 47104);
+        let third = allocator.lease().unwrap();
+        assert_eq!(third.audio_rtp_port(), 47108);
+        let fourth = allocator.lease().unwrap();
+        assert_eq!(fourth.audio_rtp_port(), 47100);
+    }
+
+    #[test]
+    fn dropped_lease_releases_its_ports() {
+        let allocator = PortAllocator::new(47200..=47203);
+
+        let lease = allocator.lease().unwrap();
+        assert!(allocator.lease().is_err());
+
+        drop(lease);
+        assert_eq!(allocator.lease().unwrap().audio_rtp_port(), 47200);
+    }
+}
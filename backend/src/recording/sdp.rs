@@ -0,0 +1,309 @@
+use mediasoup::prelude::*;
+use mediasoup::rtp_parameters::{RtpCodecParameters, RtpCodecParametersParametersValue};
+use serde::Serialize;
+use std::fmt::Write;
+
+/// Codec of an RTP stream as it is described in SDP
+#[derive(Debug, Clone)]
+pub struct SdpCodec {
+    pub payload_type: u8,
+    /// Encoding name, the part of MIME type after `/` (for instance `opus` or `H264`)
+    pub encoding_name: String,
+    pub clock_rate: u32,
+    pub channels: Option<u8>,
+    /// Format parameters, written as `a=fmtp`
+    pub parameters: Vec<(String, String)>,
+    /// RTCP feedback, written as `a=rtcp-fb`
+    pub rtcp_feedback: Vec<String>,
+}
+
+impl SdpCodec {
+    /// Whether this is a retransmission stream rather than a media codec
+    pub fn is_rtx(&self) -> bool {
+        self.encoding_name.eq_ignore_ascii_case("rtx")
+    }
+}
+
+/// Media sent to the recording process by a single consumer
+#[derive(Debug)]
+pub struct SdpMedia<'a> {
+    pub kind: MediaKind,
+    pub rtp_port: u16,
+    pub rtcp_port: u16,
+    pub rtp_parameters: &'a RtpParameters,
+}
+
+/// Get codecs of the consumer from its RTP parameters
+pub fn codecs(rtp_parameters: &RtpParameters) -> Result<Vec<SdpCodec>, String> {
+    rtp_parameters
+        .codecs
+        .iter()
+        .map(|codec| {
+            let (mime_type, payload_type, clock_rate, channels, parameters, rtcp_feedback) =
+                match codec {
+                    RtpCodecParameters::Audio {
+                        mime_type,
+                        payload_type,
+                        clock_rate,
+                        channels,
+                        parameters,
+                        rtcp_feedback,
+                    } => (
+                        to_str(mime_type)?,
+                        *payload_type,
+                        clock_rate.get(),
+                        Some(channels.get()),
+                        parameters,
+                        rtcp_feedback,
+                    ),
+                    RtpCodecParameters::Video {
+                        mime_type,
+                        payload_type,
+                        clock_rate,
+                        parameters,
+                        rtcp_feedback,
+                    } => (
+                        to_str(mime_type)?,
+                        *payload_type,
+                        clock_rate.get(),
+                        None,
+                        parameters,
+                        rtcp_feedback,
+                    ),
+                };
+
+            let encoding_name = mime_type
+                .split_once('/')
+                .map(|(_, name)| name.to_owned())
+                .ok_or_else(|| format!("Invalid MIME type {mime_type}"))?;
+
+            let parameters = parameters
+                .iter()
+                .map(|(name, value)| {
+                    let value = match value {
+                        RtpCodecParametersParametersValue::String(value) => value.to_string(),
+                        RtpCodecParametersParametersValue::Number(value) => value.to_string(),
+                    };
+                    (name.to_string(), value)
+                })
+                .collect();
+
+            let rtcp_feedback = rtcp_feedback
+                .iter()
+                .map(rtcp_feedback_attribute)
+                .collect::<Result<_, _>>()?;
+
+            Ok(SdpCodec {
+                payload_type,
+                encoding_name,
+                clock_rate,
+                channels,
+                parameters,
+                rtcp_feedback,
+            })
+        })
+        .collect()
+}
+
+/// Build SDP describing the media, only media that is actually sent needs to be passed
+pub fn build(media: &[SdpMedia<'_>]) -> Result<String, String> {
+    let mut text = "v=0\n\
+                    o=- 0 0 IN IP4 127.0.0.1\n\
+                    s=-\n\
+                    c=IN IP4 127.0.0.1\n\
+                    t=0 0\n"
+        .to_owned();
+
+    for media in media {
+        // Recording process has no use for retransmissions, it can't request them anyway
+        let codecs = codecs(media.rtp_parameters)?
+            .into_iter()
+            .filter(|codec| !codec.is_rtx())
+            .collect::<Vec<_>>();
+        let kind = match media.kind {
+            MediaKind::Audio => "audio",
+            MediaKind::Video => "video",
+        };
+        let payload_types = codecs
+            .iter()
+            .map(|codec| codec.payload_type.to_string())
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        // Writing into `String` can't fail
+        let _ = writeln!(text, "m={kind} {} RTP/AVPF {payload_types}", media.rtp_port);
+        let _ = writeln!(text, "a=rtcp:{}", media.rtcp_port);
+        let _ = writeln!(text, "a=recvonly");
+
+        for codec in &codecs {
+            let pt = codec.payload_type;
+            let _ = match codec.channels {
+                Some(channels) => writeln!(
+                    text,
+                    "a=rtpmap:{pt} {}/{}/{channels}",
+                    codec.encoding_name, codec.clock_rate
+                ),
+                None => writeln!(
+                    text,
+                    "a=rtpmap:{pt} {}/{}",
+                    codec.encoding_name, codec.clock_rate
+                ),
+            };
+            if !codec.parameters.is_empty() {
+                let parameters = codec
+                    .parameters
+                    .iter()
+                    .map(|(name, value)| format!("{name}={value}"))
+                    .collect::<Vec<_>>()
+                    .join(";");
+                let _ = writeln!(text, "a=fmtp:{pt} {parameters}");
+            }
+            for feedback in &codec.rtcp_feedback {
+                let _ = writeln!(text, "a=rtcp-fb:{pt} {feedback}");
+            }
+        }
+
+        for extension in &media.rtp_parameters.header_extensions {
+            let _ = writeln!(
+                text,
+                "a=extmap:{} {}",
+                extension.id,
+                to_str(&extension.uri)?
+            );
+        }
+    }
+
+    Ok(text)
+}
+
+/// Value of `a=rtcp-fb` attribute (without payload type)
+fn rtcp_feedback_attribute(feedback: &RtcpFeedback) -> Result<String, String> {
+    let value = serde_json::to_value(feedback)
+        .map_err(|error| format!("Failed to serialize RTCP feedback: {error}"))?;
+    let feedback_type = value.get("type").and_then(|value| value.as_str());
+    let parameter = value.get("parameter").and_then(|value| value.as_str());
+
+    match (feedback_type, parameter) {
+        (Some(feedback_type), Some(parameter)) if !parameter.is_empty() => {
+            Ok(format!("{feedback_type} {parameter}"))
+        }
+        (Some(feedback_type), _) => Ok(feedback_type.to_owned()),
+        _ => Err(format!("Unexpected RTCP feedback {value}")),
+    }
+}
+
+/// String representation of the mediasoup types that are serialized as strings, like MIME types
+/// and header extension URIs
+fn to_str<T: Serialize>(value: &T) -> Result<String, String> {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::String(value)) => Ok(value),
+        Ok(value) => Err(format!("Unexpected value {value}")),
+        Err(error) => Err(format!("Failed to serialize value: {error}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mediasoup::rtp_parameters::{MimeTypeAudio, MimeTypeVideo, RtpCodecParametersParameters};
+    use std::num::{NonZeroU32, NonZeroU8};
+
+    fn audio_parameters() -> RtpParameters {
+        RtpParameters {
+            codecs: vec![RtpCodecParameters::Audio {
+                mime_type: MimeTypeAudio::Opus,
+                payload_type: 100,
+                clock_rate: NonZeroU32::new(48000).unwrap(),
+                channels: NonZeroU8::new(2).unwrap(),
+                parameters: RtpCodecParametersParameters::from([("useinbandfec", 1_u32.into())]),
+                rtcp_feedback: vec![RtcpFeedback::TransportCc],
+            }],
+            ..RtpParameters::default()
+        }
+    }
+
+    fn video_parameters() -> RtpParameters {
+        RtpParameters {
+            codecs: vec![
+                RtpCodecParameters::Video {
+                    mime_type: MimeTypeVideo::Vp8,
+                    payload_type: 101,
+                    clock_rate: NonZeroU32::new(90000).unwrap(),
+                    parameters: RtpCodecParametersParameters::default(),
+                    rtcp_feedback: vec![
+                        RtcpFeedback::Nack,
+                        RtcpFeedback::NackPli,
+                        RtcpFeedback::CcmFir,
+                    ],
+                },
+                RtpCodecParameters::Video {
+                    mime_type: MimeTypeVideo::Rtx,
+                    payload_type: 102,
+                    clock_rate: NonZeroU32::new(90000).unwrap(),
+                    parameters: RtpCodecParametersParameters::from([("apt", 101_u32.into())]),
+                    rtcp_feedback: vec![],
+                },
+            ],
+            ..RtpParameters::default()
+        }
+    }
+
+    #[test]
+    fn build_describes_audio_and_video() {
+        let audio = audio_parameters();
+        let video = video_parameters();
+        let text = build(&[
+            SdpMedia {
+                kind: MediaKind::Audio,
+                rtp_port: 12000,
+                rtcp_port: 12001,
+                rtp_parameters: &audio,
+            },
+            SdpMedia {
+                kind: MediaKind::Video,
+                rtp_port: 12002,
+                rtcp_port: 12003,
+                rtp_parameters: &video,
+            },
+        ])
+        .unwrap();
+        let lines = text.lines().collect::<Vec<_>>();
+
+        for expected in [
+            "m=audio 12000 RTP/AVPF 100",
+            "a=rtcp:12001",
+            "a=rtpmap:100 opus/48000/2",
+            "a=fmtp:100 useinbandfec=1",
+            "a=rtcp-fb:100 transport-cc",
+            "m=video 12002 RTP/AVPF 101",
+            "a=rtcp:12003",
+            "a=rtpmap:101 VP8/90000",
+            "a=rtcp-fb:101 nack",
+            "a=rtcp-fb:101 nack pli",
+            "a=rtcp-fb:101 ccm fir",
+        ] {
+            assert!(
+                lines.contains(&expected),
+                "{expected:?} is missing in:\n{text}"
+            );
+        }
+        // Audio is described before video, in the order media was passed
+        assert!(text.find("m=audio").unwrap() < text.find("m=video").unwrap());
+    }
+
+    #[test]
+    fn build_skips_rtx() {
+        let video = video_parameters();
+        let text = build(&[SdpMedia {
+            kind: MediaKind::Video,
+            rtp_port: 12002,
+            rtcp_port: 12003,
+            rtp_parameters: &video,
+        }])
+        .unwrap();
+
+        assert!(text.contains("m=video 12002 RTP/AVPF 101\n"), "{text}");
+        assert!(!text.contains(":102 "), "{text}");
+        assert!(!text.contains("rtx"), "{text}");
+    }
+}
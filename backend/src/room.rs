@@ -1,16 +1,31 @@
+use crate::config::Config;
 use crate::participant::ParticipantId;
-use crate::recording::Recorder;
+use crate::recording::composite::{CompositeRecorder, ParticipantMedia};
+use crate::recording::port_allocator::PortAllocator;
+use crate::recording::{
+    self, Recorder, RecordingEvent, RecordingIds, RecordingMode, RecordingSummary,
+};
+use crate::worker_pool::WorkerPool;
 use event_listener_primitives::{Bag, BagOnce, HandlerId};
+use mediasoup::consumer::{ConsumerScore, ConsumerStats};
 use mediasoup::prelude::*;
-use mediasoup::worker::{WorkerLogLevel, WorkerLogTag};
+use mediasoup::producer::{ProducerScore, ProducerStat};
+use mediasoup::webrtc_transport::WebRtcTransportStat;
 use parking_lot::Mutex;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
 use std::num::{NonZeroU32, NonZeroU8};
+use std::str::FromStr;
 use std::sync::{Arc, Weak};
+use std::time::SystemTime;
 use uuid::Uuid;
 
+/// Audio quieter than this (in dBov) is not considered speech
+const SPEECH_THRESHOLD: i8 = -70;
+/// How often the loudest participant is reported (in milliseconds)
+const SPEECH_INTERVAL: u16 = 800;
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd, Deserialize, Serialize)]
 pub struct RoomId(Uuid);
 
@@ -37,29 +52,136 @@ struct Handlers {
     >,
     producer_remove:
         Bag<Arc<dyn Fn(&ParticipantId, &ProducerId) + Send + Sync>, ParticipantId, ProducerId>,
+    producer_pause: Bag<
+        Arc<dyn Fn(&ParticipantId, &ProducerId, &bool) + Send + Sync>,
+        ParticipantId,
+        ProducerId,
+        bool,
+    >,
+    recording: Bag<
+        Arc<dyn Fn(&ParticipantId, &RecordingMode, &RecordingEvent) + Send + Sync>,
+        ParticipantId,
+        RecordingMode,
+        RecordingEvent,
+    >,
+    data_producer_add: Bag<
+        Arc<dyn Fn(&ParticipantId, &String, &DataProducer) + Send + Sync>,
+        ParticipantId,
+        String,
+        DataProducer,
+    >,
+    data_producer_remove: Bag<
+        Arc<dyn Fn(&ParticipantId, &DataProducerId) + Send + Sync>,
+        ParticipantId,
+        DataProducerId,
+    >,
+    active_speaker: Bag<Arc<dyn Fn(&ParticipantId, &i8) + Send + Sync>, ParticipantId, i8>,
+    silence: Bag<Arc<dyn Fn() + Send + Sync>>,
+    worker_close: BagOnce<Box<dyn FnOnce() + Send>>,
     close: BagOnce<Box<dyn FnOnce() + Send>>,
 }
 
 #[derive(Debug, Default)]
 struct Client {
     name: String,
+    /// WebRTC transports of the participant, kept for stats
+    transports: Vec<WebRtcTransport>,
     producers: Vec<Producer>,
+    /// Data producers (SCTP data channels) others can consume
+    data_producers: Vec<DataProducer>,
+    /// Consumers of the participant, kept for stats
+    consumers: HashMap<ConsumerId, Consumer>,
+    scores: ParticipantScores,
     recorder: Recorder,
 }
 
+/// Latest scores of the participant's producers and consumers, reflecting the quality of their
+/// connection
+#[derive(Debug, Clone, Default)]
+pub struct ParticipantScores {
+    pub producers: HashMap<ProducerId, Vec<ProducerScore>>,
+    pub consumers: HashMap<ConsumerId, ConsumerScore>,
+}
+
+/// Stats of the participant's transports, producers and consumers as reported by mediasoup
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ParticipantStats {
+    pub transports: Vec<TransportReport>,
+    pub producers: Vec<ProducerReport>,
+    pub consumers: Vec<ConsumerReport>,
+}
+
+/// Stats of a single transport, including ICE/DTLS state, bitrates and RTT
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransportReport {
+    pub id: TransportId,
+    pub stats: Vec<WebRtcTransportStat>,
+}
+
+/// Stats of a single producer, including packet loss and jitter of each stream
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProducerReport {
+    pub id: ProducerId,
+    pub kind: MediaKind,
+    pub score: Vec<ProducerScore>,
+    pub stats: Vec<ProducerStat>,
+}
+
+/// Stats of a single consumer, with stats of its producer's stream if available
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConsumerReport {
+    pub id: ConsumerId,
+    pub producer_id: ProducerId,
+    pub kind: MediaKind,
+    pub score: Option<ConsumerScore>,
+    pub stats: ConsumerStats,
+}
+
 // Room 構造体がメンバを非公開にして Arc で複数スレッド対応できるようにするための
 // 内部的な構造体
 struct Inner {
     id: RoomId,
+    created_at: SystemTime,
+    config: Arc<Config>,
+    /// Ports for recordings, shared by all the rooms
+    recording_ports: PortAllocator,
     router: Router,
+    /// Observer of all audio producers in the room, detects who is speaking
+    audio_level_observer: AudioLevelObserver,
+    /// Owners of the producers added to `audio_level_observer`, kept apart from `clients` so that
+    /// observer callbacks never wait for the clients lock
+    speakers: Mutex<HashMap<ProducerId, ParticipantId>>,
     handlers: Handlers,
     clients: Mutex<HashMap<ParticipantId, Client>>,
+    /// Recording of the whole room, if any
+    composite: async_lock::Mutex<Option<CompositeRecorder>>,
+    /// Participant that started recording of the whole room and ID of the recording, available
+    /// without waiting for `composite` lock
+    room_recording: Mutex<Option<(ParticipantId, String)>>,
+    /// IDs of recordings in progress in either mode, shared by all the rooms and released once
+    /// recording is stopped or has failed
+    recording_ids: RecordingIds,
+    /// Changes of the room that recording of the whole room hasn't followed yet
+    room_recording_updates: Mutex<RoomRecordingUpdates>,
+}
+
+/// State of the worker that applies changes of the room to its recording, changes that happen
+/// while it is busy are coalesced into a single update
+#[derive(Debug, Default)]
+struct RoomRecordingUpdates {
+    running: bool,
+    pending: bool,
 }
 
 impl fmt::Debug for Inner {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("Inner")
             .field("id", &self.id)
+            .field("created_at", &self.created_at)
             .field("handlers", &"...")
             .field("clients", &self.clients)
             .finish()
@@ -83,52 +205,123 @@ pub struct Room {
 
 impl Room {
     /// Create new `Room` with random `RoomId`
-    pub async fn new(worker_manager: &WorkerManager) -> Result<Self, String> {
-        Self::new_with_id(worker_manager, RoomId::new()).await
-    }
-
-    /// Create new `Room` with a specific `RoomId`
-    pub async fn new_with_id(worker_manager: &WorkerManager, id: RoomId) -> Result<Room, String> {
-        let worker = worker_manager
-            .create_worker({
-                let mut settings = WorkerSettings::default();
-                settings.rtc_ports_range = 50000..=51000;
-                settings.log_level = WorkerLogLevel::Debug;
-                settings.log_tags = vec![
-                    WorkerLogTag::Info,
-                    WorkerLogTag::Ice,
-                    WorkerLogTag::Dtls,
-                    WorkerLogTag::Rtp,
-                    WorkerLogTag::Srtp,
-                    WorkerLogTag::Rtcp,
-                    WorkerLogTag::Rtx,
-                    WorkerLogTag::Bwe,
-                    WorkerLogTag::Score,
-                    WorkerLogTag::Simulcast,
-                    WorkerLogTag::Svc,
-                    WorkerLogTag::Sctp,
-                    WorkerLogTag::Message,
-                ];
-
-                settings
-            })
-            .await
-            .map_err(|error| format!("Failed to create worker: {error}"))?;
-        let router = worker
-            .create_router(RouterOptions::new(media_codecs()))
+    pub async fn new(
+        worker_pool: &WorkerPool,
+        config: Arc<Config>,
+        recording_ports: PortAllocator,
+        recording_ids: RecordingIds,
+    ) -> Result<Self, String> {
+        Self::new_with_id(
+            worker_pool,
+            config,
+            recording_ports,
+            recording_ids,
+            RoomId::new(),
+        )
+        .await
+    }
+
+    /// Create new `Room` with a specific `RoomId`, its router is placed on the least loaded
+    /// worker of the pool
+    pub async fn new_with_id(
+        worker_pool: &WorkerPool,
+        config: Arc<Config>,
+        recording_ports: PortAllocator,
+        recording_ids: RecordingIds,
+        id: RoomId,
+    ) -> Result<Room, String> {
+        let router = worker_pool
+            .create_router(RouterOptions::new(media_codecs(
+                &config.router.codecs,
+                &config.router.rtcp_feedback,
+            )))
+            .await?;
+
+        let mut observer_options = AudioLevelObserverOptions::default();
+        observer_options.threshold = SPEECH_THRESHOLD;
+        observer_options.interval = SPEECH_INTERVAL;
+        let audio_level_observer = router
+            .create_audio_level_observer(observer_options)
             .await
-            .map_err(|error| format!("Failed to create router: {error}"))?;
+            .map_err(|error| format!("Failed to create audio level observer: {error}"))?;
 
         println!("Room {id} created");
 
-        Ok(Self {
+        let room = Self {
             inner: Arc::new(Inner {
                 id,
+                created_at: SystemTime::now(),
+                config,
+                recording_ports,
                 router,
+                audio_level_observer,
+                speakers: Mutex::default(),
                 handlers: Handlers::default(),
                 clients: Mutex::default(),
+                composite: async_lock::Mutex::default(),
+                room_recording: Mutex::default(),
+                recording_ids,
+                room_recording_updates: Mutex::default(),
             }),
-        })
+        };
+
+        // Room can't work without its worker, let participants know when it dies
+        room.inner
+            .router
+            .on_worker_close({
+                let room = room.downgrade();
+
+                move || {
+                    if let Some(room) = room.upgrade() {
+                        eprintln!("Room {} lost its worker", room.id());
+                        room.inner.handlers.worker_close.call_simple();
+                    }
+                }
+            })
+            .detach();
+
+        // Observer reports only the loudest producer (`max_entries` is 1)
+        room.inner
+            .audio_level_observer
+            .on_volumes({
+                let room = room.downgrade();
+
+                move |volumes| {
+                    let Some(room) = room.upgrade() else {
+                        return;
+                    };
+                    for volume in volumes {
+                        let participant_id = room
+                            .inner
+                            .speakers
+                            .lock()
+                            .get(&volume.producer.id())
+                            .copied();
+                        if let Some(participant_id) = participant_id {
+                            room.inner
+                                .handlers
+                                .active_speaker
+                                .call_simple(&participant_id, &volume.volume);
+                        }
+                    }
+                }
+            })
+            .detach();
+
+        room.inner
+            .audio_level_observer
+            .on_silence({
+                let room = room.downgrade();
+
+                move || {
+                    if let Some(room) = room.upgrade() {
+                        room.inner.handlers.silence.call_simple();
+                    }
+                }
+            })
+            .detach();
+
+        Ok(room)
     }
 
     /// ID of the room
@@ -136,11 +329,151 @@ impl Room {
         self.inner.id
     }
 
+    /// Time when the room was created
+    pub fn created_at(&self) -> SystemTime {
+        self.inner.created_at
+    }
+
+    /// Whether the room is closed and can't be used anymore (its worker has died)
+    pub fn is_closed(&self) -> bool {
+        self.inner.router.closed()
+    }
+
+    /// Server configuration the room was created with
+    pub fn config(&self) -> &Config {
+        &self.inner.config
+    }
+
     /// Get router associated with this room
     pub fn router(&self) -> &Router {
         &self.inner.router
     }
 
+    /// Keep WebRTC transport of the participant, so that its stats are available
+    pub fn add_transport(&self, participant_id: ParticipantId, transport: WebRtcTransport) {
+        let mut clients = self.inner.clients.lock();
+        let client = clients.entry(participant_id).or_default();
+        client.transports.push(transport);
+    }
+
+    /// Keep consumer of the participant, so that its stats and score are available until it is
+    /// removed with `remove_consumer`
+    pub fn add_consumer(&self, participant_id: ParticipantId, consumer: Consumer) {
+        let mut clients = self.inner.clients.lock();
+        let client = clients.entry(participant_id).or_default();
+        client.consumers.insert(consumer.id(), consumer);
+    }
+
+    /// Forget consumer of the participant, consumer is closed once the participant drops it too
+    pub fn remove_consumer(&self, participant_id: &ParticipantId, consumer_id: &ConsumerId) {
+        let mut clients = self.inner.clients.lock();
+        if let Some(client) = clients.get_mut(participant_id) {
+            client.consumers.remove(consumer_id);
+            client.scores.consumers.remove(consumer_id);
+        }
+    }
+
+    /// Remember the latest score of the participant's producer
+    pub fn set_producer_score(
+        &self,
+        participant_id: &ParticipantId,
+        producer_id: ProducerId,
+        score: Vec<ProducerScore>,
+    ) {
+        let mut clients = self.inner.clients.lock();
+        if let Some(client) = clients.get_mut(participant_id) {
+            client.scores.producers.insert(producer_id, score);
+        }
+    }
+
+    /// Remember the latest score of the participant's consumer
+    pub fn set_consumer_score(
+        &self,
+        participant_id: &ParticipantId,
+        consumer_id: ConsumerId,
+        score: ConsumerScore,
+    ) {
+        let mut clients = self.inner.clients.lock();
+        if let Some(client) = clients.get_mut(participant_id) {
+            client.scores.consumers.insert(consumer_id, score);
+        }
+    }
+
+    /// Get the latest scores of the participant's producers and consumers
+    pub fn get_scores(&self, participant_id: &ParticipantId) -> Option<ParticipantScores> {
+        let clients = self.inner.clients.lock();
+        clients
+            .get(participant_id)
+            .map(|client| client.scores.clone())
+    }
+
+    /// Collect stats of the participant's transports, producers and consumers, `None` if there
+    /// is no such participant in the room
+    pub async fn get_participant_stats(
+        &self,
+        participant_id: &ParticipantId,
+    ) -> Result<Option<ParticipantStats>, String> {
+        let (transports, producers, consumers, scores) = {
+            let clients = self.inner.clients.lock();
+            let Some(client) = clients.get(participant_id) else {
+                return Ok(None);
+            };
+            (
+                client.transports.clone(),
+                client.producers.clone(),
+                client.consumers.values().cloned().collect::<Vec<_>>(),
+                client.scores.clone(),
+            )
+        };
+
+        let mut stats = ParticipantStats {
+            transports: Vec::with_capacity(transports.len()),
+            producers: Vec::with_capacity(producers.len()),
+            consumers: Vec::with_capacity(consumers.len()),
+        };
+
+        for transport in transports {
+            stats.transports.push(TransportReport {
+                id: transport.id(),
+                stats: transport.get_stats().await.map_err(|error| {
+                    format!(
+                        "Failed to get stats of transport {}: {error}",
+                        transport.id()
+                    )
+                })?,
+            });
+        }
+
+        for producer in producers {
+            stats.producers.push(ProducerReport {
+                id: producer.id(),
+                kind: producer.kind(),
+                score: scores
+                    .producers
+                    .get(&producer.id())
+                    .cloned()
+                    .unwrap_or_default(),
+                stats: producer.get_stats().await.map_err(|error| {
+                    format!("Failed to get stats of producer {}: {error}", producer.id())
+                })?,
+            });
+        }
+
+        for consumer in consumers {
+            stats.consumers.push(ConsumerReport {
+                id: consumer.id(),
+                producer_id: consumer.producer_id(),
+                kind: consumer.kind(),
+                score: scores.consumers.get(&consumer.id()).cloned(),
+                stats: consumer.get_stats().await.map_err(|error| {
+                    format!("Failed to get stats of consumer {}: {error}", consumer.id())
+                })?,
+            });
+        }
+
+        Ok(Some(stats))
+    }
+
     /// Add producer to the room, this will trigger notifications to other participants that
     /// will be able to consume it
     pub fn set_participant_name(&self, participant_id: ParticipantId, name: String) {
@@ -151,39 +484,231 @@ impl Room {
 
     /// Add producer to the room, this will trigger notifications to other participants that
     /// will be able to consume it
-    pub fn add_producer(&self, participant_id: ParticipantId, producer: Producer) {
+    pub async fn add_producer(&self, participant_id: ParticipantId, producer: Producer) {
+        {
+            let mut clients = self.inner.clients.lock();
+            // Participant may have left while producer was being created, it is closed once
+            // dropped
+            let Some(client) = clients.get_mut(&participant_id) else {
+                return;
+            };
+
+            client.producers.push(producer.clone());
+
+            let name = client.name.clone();
+
+            self.inner
+                .handlers
+                .producer_add
+                .call_simple(&participant_id, &name, &producer);
+        }
+
+        // Producer is removed from the observer automatically when it is closed, audio of screen
+        // share is not speech
+        if ProducerSource::of(&producer) == ProducerSource::Microphone {
+            self.inner
+                .speakers
+                .lock()
+                .insert(producer.id(), participant_id);
+            let options = RtpObserverAddProducerOptions::new(producer.id());
+            if let Err(error) = self.inner.audio_level_observer.add_producer(options).await {
+                eprintln!(
+                    "Failed to observe audio level of producer {}: {error}",
+                    producer.id()
+                );
+            }
+        }
+
+        self.update_room_recording();
+    }
+
+    /// Add data producer to the room, others are notified about it the same way as about
+    /// producers
+    pub fn add_data_producer(&self, participant_id: ParticipantId, data_producer: DataProducer) {
         let mut clients = self.inner.clients.lock();
-        let client = clients.entry(participant_id).or_default();
+        // The same as for producers, participant may be gone already
+        let Some(client) = clients.get_mut(&participant_id) else {
+            return;
+        };
 
-        client.producers.push(producer.clone());
+        client.data_producers.push(data_producer.clone());
 
         let name = client.name.clone();
 
         self.inner
             .handlers
-            .producer_add
-            .call_simple(&participant_id, &name, &producer);
+            .data_producer_add
+            .call_simple(&participant_id, &name, &data_producer);
+
+        std::mem::drop(clients);
+
+        // Data producer is closed when it is dropped by both the room and the connection or when
+        // its transport is closed, `clients` must not be locked in mediasoup callbacks
+        let data_producer_id = data_producer.id();
+        data_producer
+            .on_close({
+                let room = self.downgrade();
+
+                move || {
+                    if let Some(room) = room.upgrade() {
+                        room.inner
+                            .handlers
+                            .data_producer_remove
+                            .call_simple(&participant_id, &data_producer_id);
+                    }
+                }
+            })
+            .detach();
+    }
+
+    /// Remove data producer of the participant from the room, others are notified once it is
+    /// closed
+    pub fn close_data_producer(
+        &self,
+        participant_id: &ParticipantId,
+        data_producer_id: &DataProducerId,
+    ) -> Result<(), String> {
+        let mut clients = self.inner.clients.lock();
+        let client = clients
+            .get_mut(participant_id)
+            .ok_or_else(|| format!("Data producer {data_producer_id} doesn't exist"))?;
+        let index = client
+            .data_producers
+            .iter()
+            .position(|data_producer| &data_producer.id() == data_producer_id)
+            .ok_or_else(|| format!("Data producer {data_producer_id} doesn't exist"))?;
+        let data_producer = client.data_producers.remove(index);
+
+        std::mem::drop(clients);
+        std::mem::drop(data_producer);
+
+        Ok(())
+    }
+
+    /// Pause or resume producer of the participant, other participants are notified about it
+    pub async fn set_producer_paused(
+        &self,
+        participant_id: &ParticipantId,
+        producer_id: &ProducerId,
+        paused: bool,
+    ) -> Result<(), String> {
+        let producer = self
+            .inner
+            .clients
+            .lock()
+            .get(participant_id)
+            .and_then(|client| {
+                client
+                    .producers
+                    .iter()
+                    .find(|producer| &producer.id() == producer_id)
+                    .cloned()
+            })
+            .ok_or_else(|| format!("Producer {producer_id} doesn't exist"))?;
+
+        if paused {
+            producer
+                .pause()
+                .await
+                .map_err(|error| format!("Failed to pause producer {producer_id}: {error}"))?;
+        } else {
+            producer
+                .resume()
+                .await
+                .map_err(|error| format!("Failed to resume producer {producer_id}: {error}"))?;
+        }
+
+        self.inner
+            .handlers
+            .producer_pause
+            .call_simple(participant_id, producer_id, &paused);
+
+        Ok(())
+    }
+
+    /// Remove producer of the participant from the room, other participants are notified about
+    /// it the same way as when the participant leaves
+    pub fn close_producer(
+        &self,
+        participant_id: &ParticipantId,
+        producer_id: &ProducerId,
+    ) -> Result<(), String> {
+        let mut clients = self.inner.clients.lock();
+        let client = clients
+            .get_mut(participant_id)
+            .ok_or_else(|| format!("Producer {producer_id} doesn't exist"))?;
+        let index = client
+            .producers
+            .iter()
+            .position(|producer| &producer.id() == producer_id)
+            .ok_or_else(|| format!("Producer {producer_id} doesn't exist"))?;
+        let _ = client.producers.remove(index);
+        client.scores.producers.remove(producer_id);
+        self.inner.speakers.lock().remove(producer_id);
+
+        self.inner
+            .handlers
+            .producer_remove
+            .call_simple(participant_id, producer_id);
+
+        std::mem::drop(clients);
+
+        self.update_room_recording();
+
+        Ok(())
     }
 
-    /// Remove participant and all of its associated producers
+    /// Remove participant and all of its associated producers, recording of the participant is
+    /// stopped if it is still in progress
     pub fn remove_participant(&self, participant_id: &ParticipantId) {
         let client = self.inner.clients.lock().remove(participant_id);
-        let Some(client) = client else {
+        let Some(mut client) = client else {
             return;
         };
 
-        for producer in client.producers {
+        for producer in &client.producers {
             let producer_id = &producer.id();
+            self.inner.speakers.lock().remove(producer_id);
             self.inner
                 .handlers
                 .producer_remove
                 .call_simple(participant_id, producer_id);
         }
+
+        if client.recorder.is_recording() {
+            let room = self.clone();
+            let participant_id = *participant_id;
+
+            // Stopping recording waits for the recording process to finish, don't block the
+            // caller (usually participant connection being dropped) with it
+            std::thread::spawn(move || {
+                let recording_id = client.recorder.recording_id.clone();
+                let result = futures_lite::future::block_on(client.recorder.stop_recording());
+                room.notify_recording_stopped(
+                    &participant_id,
+                    RecordingMode::Participant,
+                    recording_id,
+                    result,
+                );
+            });
+        }
+
+        self.update_room_recording();
+    }
+
+    /// Get all participants of the room with their names
+    pub fn get_participants(&self) -> Vec<(ParticipantId, String)> {
+        let clients = self.inner.clients.lock();
+
+        clients
+            .iter()
+            .map(|(participant_id, client)| (*participant_id, client.name.clone()))
+            .collect()
     }
 
     /// Get all producers of all participants, useful when new participant connects and needs to
     /// consume tracks of everyone who is already in the room
-    pub fn get_all_producers(&self) -> Vec<(ParticipantId, String, ProducerId)> {
+    pub fn get_all_producers(&self) -> Vec<(ParticipantId, String, Producer)> {
         let clients = self.inner.clients.lock();
 
         clients
@@ -194,11 +719,55 @@ impl Room {
                 client
                     .producers
                     .iter()
-                    .map(move |producer| (participant_id, name.clone(), producer.id()))
+                    .map(move |producer| (participant_id, name.clone(), producer.clone()))
             })
             .collect()
     }
 
+    /// Get all data producers of all participants, useful when new participant connects and needs
+    /// to consume data channels of everyone else
+    pub fn get_all_data_producers(&self) -> Vec<(ParticipantId, String, DataProducer)> {
+        let clients = self.inner.clients.lock();
+
+        clients
+            .iter()
+            .flat_map(|(participant_id, client)| {
+                let participant_id = *participant_id;
+                let name = client.name.clone();
+                // Data producers whose transport was closed are already reported as removed
+                client
+                    .data_producers
+                    .iter()
+                    .filter(|data_producer| !data_producer.closed())
+                    .map(move |data_producer| (participant_id, name.clone(), data_producer.clone()))
+            })
+            .collect()
+    }
+
+    /// Get recordings that are currently in progress in the room, recording of the whole room
+    /// is reported with the participant that has started it
+    pub fn get_recordings(&self) -> Vec<(ParticipantId, RecordingMode, String)> {
+        let clients = self.inner.clients.lock();
+
+        let mut recordings = clients
+            .iter()
+            .filter(|(_, client)| client.recorder.is_recording())
+            .map(|(participant_id, client)| {
+                (
+                    *participant_id,
+                    RecordingMode::Participant,
+                    client.recorder.recording_id.clone(),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        if let Some((participant_id, recording_id)) = self.inner.room_recording.lock().clone() {
+            recordings.push((participant_id, RecordingMode::Room, recording_id));
+        }
+
+        recordings
+    }
+
     /// Subscribe to notifications when new producer is added to the room
     pub fn on_producer_add<F: Fn(&ParticipantId, &String, &Producer) + Send + Sync + 'static>(
         &self,
@@ -215,6 +784,65 @@ impl Room {
         self.inner.handlers.producer_remove.add(Arc::new(callback))
     }
 
+    /// Subscribe to notifications when new data producer is added to the room
+    pub fn on_data_producer_add<F>(&self, callback: F) -> HandlerId
+    where
+        F: Fn(&ParticipantId, &String, &DataProducer) + Send + Sync + 'static,
+    {
+        self.inner
+            .handlers
+            .data_producer_add
+            .add(Arc::new(callback))
+    }
+
+    /// Subscribe to notifications when data producer is removed from the room
+    pub fn on_data_producer_remove<F>(&self, callback: F) -> HandlerId
+    where
+        F: Fn(&ParticipantId, &DataProducerId) + Send + Sync + 'static,
+    {
+        self.inner
+            .handlers
+            .data_producer_remove
+            .add(Arc::new(callback))
+    }
+
+    /// Subscribe to notifications when producer is paused (`true`) or resumed (`false`)
+    pub fn on_producer_pause<F>(&self, callback: F) -> HandlerId
+    where
+        F: Fn(&ParticipantId, &ProducerId, &bool) + Send + Sync + 'static,
+    {
+        self.inner.handlers.producer_pause.add(Arc::new(callback))
+    }
+
+    /// Subscribe to notifications when recording of any participant of the room is started,
+    /// stopped or fails
+    pub fn on_recording<F>(&self, callback: F) -> HandlerId
+    where
+        F: Fn(&ParticipantId, &RecordingMode, &RecordingEvent) + Send + Sync + 'static,
+    {
+        self.inner.handlers.recording.add(Arc::new(callback))
+    }
+
+    /// Subscribe to notifications about the loudest participant and its volume (in dBov), sent
+    /// periodically while anyone is speaking
+    pub fn on_active_speaker<F: Fn(&ParticipantId, &i8) + Send + Sync + 'static>(
+        &self,
+        callback: F,
+    ) -> HandlerId {
+        self.inner.handlers.active_speaker.add(Arc::new(callback))
+    }
+
+    /// Subscribe to notification when nobody in the room is speaking anymore
+    pub fn on_silence<F: Fn() + Send + Sync + 'static>(&self, callback: F) -> HandlerId {
+        self.inner.handlers.silence.add(Arc::new(callback))
+    }
+
+    /// Subscribe to notification when the worker of the room dies, after which room is
+    /// unusable and participants should disconnect
+    pub fn on_worker_close<F: FnOnce() + Send + 'static>(&self, callback: F) -> HandlerId {
+        self.inner.handlers.worker_close.add(Box::new(callback))
+    }
+
     /// Subscribe to notification when room is closed
     pub fn on_close<F: FnOnce() + Send + 'static>(&self, callback: F) -> HandlerId {
         self.inner.handlers.close.add(Box::new(callback))
@@ -228,54 +856,489 @@ impl Room {
         }
     }
 
+    /// Start recording of the participant, `output_name` is validated (or generated when
+    /// missing) and returned as the ID of the recording
     pub async fn start_recording(
         &mut self,
         participant_id: &ParticipantId,
-        output_name: &str,
+        output_name: Option<&str>,
+    ) -> Result<String, String> {
+        let recording_id = recording::recording_id(output_name)?;
+        self.reserve_recording_id(&recording_id)?;
+
+        // Recording that hasn't started is only reported to the participant that requested it,
+        // others would take `Failed` for the end of the recording in progress
+        if let Err(reason) = self.start_recorder(participant_id, &recording_id).await {
+            self.release_recording_id(&recording_id);
+            return Err(reason);
+        }
+        self.call_recording_handlers(
+            participant_id,
+            RecordingMode::Participant,
+            &RecordingEvent::Started {
+                recording_id: recording_id.clone(),
+            },
+        );
+
+        // Failure of the process that exits before the recorder is stored is ignored by
+        // `fail_recording`, it has to be detected here
+        let exited = self
+            .inner
+            .clients
+            .lock()
+            .get(participant_id)
+            .and_then(|client| client.recorder.exited_process_status());
+        if let Some(status) = exited {
+            self.fail_recording(
+                participant_id,
+                recording_id.clone(),
+                format!("Recording process exited right after start with {status}"),
+            )
+            .await;
+        }
+
+        Ok(recording_id)
+    }
+
+    async fn start_recorder(
+        &self,
+        participant_id: &ParticipantId,
+        recording_id: &str,
     ) -> Result<(), String> {
-        let mut clients = self.inner.clients.lock();
-        let found = clients.get_mut(participant_id);
+        let (audio_producer, video_producer) = {
+            let clients = self.inner.clients.lock();
+            let Some(client) = clients.get(participant_id) else {
+                return Err("Invalid participant is specified.".to_owned());
+            };
 
-        let Some(client) = found else {
-            return Err("Invalid participant is specified.".to_owned());
-        };
+            if client.recorder.is_recording() {
+                return Err("Recording is already in progress".to_owned());
+            }
 
-        let audio_producer = client
-            .producers
-            .iter()
-            .find(|p| p.kind() == MediaKind::Audio);
-        let video_producer = client
-            .producers
-            .iter()
-            .find(|p| p.kind() == MediaKind::Video);
+            (
+                recorded_producer(&client.producers, MediaKind::Audio).cloned(),
+                recorded_producer(&client.producers, MediaKind::Video).cloned(),
+            )
+        };
 
         log::debug!(
             "recording target: {:?}, {:?}.",
             audio_producer,
             video_producer
         );
-        let mut new_recorder = Recorder::new(self.router(), audio_producer, video_producer).await?;
-        new_recorder.start_recording(output_name).await?;
-        client.recorder = new_recorder;
+        let mut new_recorder = Recorder::new(
+            self.router(),
+            &self.inner.config.recording,
+            &self.inner.recording_ports,
+            audio_producer.as_ref(),
+            video_producer.as_ref(),
+        )
+        .await?;
 
-        log::debug!("recording started.");
+        // Recording process may die at any time, including right after it is started
+        new_recorder
+            .on_failed({
+                let room = self.downgrade();
+                let participant_id = *participant_id;
+                let recording_id = recording_id.to_owned();
 
-        std::mem::drop(clients);
+                move |reason| {
+                    if let Some(room) = room.upgrade() {
+                        futures_lite::future::block_on(room.fail_recording(
+                            &participant_id,
+                            recording_id.clone(),
+                            reason.clone(),
+                        ));
+                    }
+                }
+            })
+            .detach();
+        new_recorder.start_recording(recording_id).await?;
+
+        let rejected = match self.inner.clients.lock().get_mut(participant_id) {
+            // Participant has left while recording was being started
+            None => Some("Invalid participant is specified."),
+            // Another recording was started concurrently, it is the one that is kept
+            Some(client) if client.recorder.is_recording() => {
+                Some("Recording is already in progress")
+            }
+            Some(client) => {
+                client.recorder = std::mem::take(&mut new_recorder);
+                None
+            }
+        };
+        if let Some(reason) = rejected {
+            let _ = new_recorder.stop_recording().await;
+            return Err(reason.to_owned());
+        }
+
+        log::debug!("recording {recording_id} started.");
 
         Ok(())
     }
 
-    pub async fn stop_recording(&mut self, participant_id: &ParticipantId) -> Result<(), String> {
+    /// Stop recording of the participant and get information about the recorded file
+    pub async fn stop_recording(
+        &mut self,
+        participant_id: &ParticipantId,
+    ) -> Result<RecordingSummary, String> {
+        let mut recorder = {
+            let mut clients = self.inner.clients.lock();
+            let Some(client) = clients.get_mut(participant_id) else {
+                return Err("Invalid participant is specified.".to_owned());
+            };
+
+            if !client.recorder.is_recording() {
+                return Err("Recording is not in progress".to_owned());
+            }
+
+            std::mem::take(&mut client.recorder)
+        };
+
+        // Stopping waits for the recording process to exit, clients must not be locked meanwhile
+        let recording_id = recorder.recording_id.clone();
+        let result = recorder.stop_recording().await;
+        self.restore_recorder(participant_id, recorder);
+
+        self.notify_recording_stopped(
+            participant_id,
+            RecordingMode::Participant,
+            recording_id,
+            result.clone(),
+        );
+
+        result
+    }
+
+    /// Clean up recording whose process has exited on its own and let everyone know about it
+    async fn fail_recording(
+        &self,
+        participant_id: &ParticipantId,
+        recording_id: String,
+        reason: String,
+    ) {
+        let mut recorder = {
+            let mut clients = self.inner.clients.lock();
+            let Some(client) = clients.get_mut(participant_id) else {
+                return;
+            };
+
+            // Recording may have been stopped or replaced in the meantime
+            if !client.recorder.is_recording() || client.recorder.recording_id != recording_id {
+                return;
+            }
+
+            std::mem::take(&mut client.recorder)
+        };
+
+        recorder.fail(reason.clone()).await;
+        self.restore_recorder(participant_id, recorder);
+
+        self.call_recording_handlers(
+            participant_id,
+            RecordingMode::Participant,
+            &RecordingEvent::Failed {
+                recording_id,
+                reason,
+            },
+        );
+    }
+
+    /// Put recorder taken out of the participant back once it is not used anymore, unless the
+    /// participant has left or started another recording meanwhile
+    fn restore_recorder(&self, participant_id: &ParticipantId, recorder: Recorder) {
         let mut clients = self.inner.clients.lock();
-        let found = clients.get_mut(participant_id);
+        if let Some(client) = clients.get_mut(participant_id) {
+            if !client.recorder.is_recording() {
+                client.recorder = recorder;
+            }
+        }
+    }
 
-        let Some(client) = found else {
-            return Err("Invalid participant is specified.".to_owned());
+    /// Start recording of all participants of the room into a single file, including the ones
+    /// that join later
+    pub async fn start_room_recording(
+        &self,
+        participant_id: &ParticipantId,
+        output_name: Option<&str>,
+    ) -> Result<String, String> {
+        let recording_id = recording::recording_id(output_name)?;
+
+        let mut composite = self.inner.composite.lock().await;
+        if composite.is_some() {
+            return Err("Room is already being recorded".to_owned());
+        }
+        self.reserve_recording_id(&recording_id)?;
+
+        match self.start_composite(participant_id, &recording_id).await {
+            Ok(recorder) => {
+                *composite = Some(recorder);
+                *self.inner.room_recording.lock() = Some((*participant_id, recording_id.clone()));
+            }
+            Err(reason) => {
+                self.release_recording_id(&recording_id);
+                return Err(reason);
+            }
+        }
+
+        std::mem::drop(composite);
+
+        log::debug!("room recording {recording_id} started.");
+
+        self.call_recording_handlers(
+            participant_id,
+            RecordingMode::Room,
+            &RecordingEvent::Started {
+                recording_id: recording_id.clone(),
+            },
+        );
+
+        Ok(recording_id)
+    }
+
+    async fn start_composite(
+        &self,
+        participant_id: &ParticipantId,
+        recording_id: &str,
+    ) -> Result<CompositeRecorder, String> {
+        let mut recorder = CompositeRecorder::new(
+            self.router(),
+            &self.inner.config.recording,
+            &self.inner.recording_ports,
+            recording_id,
+            *participant_id,
+        )?;
+
+        recorder
+            .on_failed({
+                let room = self.downgrade();
+                let recording_id = recording_id.to_owned();
+
+                move |reason| {
+                    if let Some(room) = room.upgrade() {
+                        futures_lite::future::block_on(
+                            room.fail_room_recording(&recording_id, reason.clone()),
+                        );
+                    }
+                }
+            })
+            .detach();
+        recorder.update(self.participant_media()).await?;
+
+        Ok(recorder)
+    }
+
+    /// Stop recording of the whole room, any participant can stop it
+    pub async fn stop_room_recording(
+        &self,
+        participant_id: &ParticipantId,
+    ) -> Result<RecordingSummary, String> {
+        let recorder = self.inner.composite.lock().await.take();
+        let Some(recorder) = recorder else {
+            return Err("Room is not being recorded".to_owned());
         };
+        self.inner.room_recording.lock().take();
+
+        let recording_id = recorder.recording_id().to_owned();
+        // Stopping waits for ffmpeg to exit and concatenates segments, which would stall the
+        // arbiter that serves WebSocket connections
+        let result = tokio::task::spawn_blocking(move || recorder.stop())
+            .await
+            .unwrap_or_else(|error| Err(format!("Failed to stop room recording: {error}")));
 
-        client.recorder.stop_recording().await?;
+        self.notify_recording_stopped(
+            participant_id,
+            RecordingMode::Room,
+            recording_id,
+            result.clone(),
+        );
 
-        Ok(())
+        result
+    }
+
+    /// Let recording of the whole room follow producers being added and removed, recording is
+    /// finished once everyone has left
+    fn update_room_recording(&self) {
+        if self.inner.room_recording.lock().is_none() {
+            return;
+        }
+
+        {
+            let mut updates = self.inner.room_recording_updates.lock();
+            updates.pending = true;
+            if updates.running {
+                return;
+            }
+            updates.running = true;
+        }
+
+        let room = self.clone();
+        std::thread::spawn(move || {
+            futures_lite::future::block_on(async move {
+                loop {
+                    {
+                        let mut updates = room.inner.room_recording_updates.lock();
+                        if !updates.pending {
+                            updates.running = false;
+                            return;
+                        }
+                        updates.pending = false;
+                    }
+
+                    room.apply_room_recording_update().await;
+                }
+            });
+        });
+    }
+
+    /// Restart recording of the whole room with current media of the participants
+    async fn apply_room_recording_update(&self) {
+        let mut composite = self.inner.composite.lock().await;
+        let Some(recorder) = composite.as_mut() else {
+            return;
+        };
+        let started_by = recorder.started_by();
+        let recording_id = recorder.recording_id().to_owned();
+
+        if self.inner.clients.lock().is_empty() {
+            let recorder = composite.take();
+            self.inner.room_recording.lock().take();
+            std::mem::drop(composite);
+
+            if let Some(recorder) = recorder {
+                self.notify_recording_stopped(
+                    &started_by,
+                    RecordingMode::Room,
+                    recording_id,
+                    recorder.stop(),
+                );
+            }
+            return;
+        }
+
+        if let Err(reason) = recorder.update(self.participant_media()).await {
+            composite.take();
+            self.inner.room_recording.lock().take();
+            std::mem::drop(composite);
+
+            log::error!("room recording {recording_id} failed: {reason}");
+            self.call_recording_handlers(
+                &started_by,
+                RecordingMode::Room,
+                &RecordingEvent::Failed {
+                    recording_id,
+                    reason,
+                },
+            );
+        }
+    }
+
+    /// Clean up recording of the whole room whose process has exited on its own
+    async fn fail_room_recording(&self, recording_id: &str, reason: String) {
+        let mut composite = self.inner.composite.lock().await;
+
+        // Recording may have been stopped or moved on to the next segment in the meantime
+        let failed = composite.as_ref().is_some_and(|recorder| {
+            recorder.recording_id() == recording_id && recorder.has_exited()
+        });
+        if !failed {
+            return;
+        }
+
+        let Some(recorder) = composite.take() else {
+            return;
+        };
+        self.inner.room_recording.lock().take();
+        std::mem::drop(composite);
+
+        let started_by = recorder.started_by();
+        // Segments recorded so far are left on disk
+        std::mem::drop(recorder);
+
+        self.call_recording_handlers(
+            &started_by,
+            RecordingMode::Room,
+            &RecordingEvent::Failed {
+                recording_id: recording_id.to_owned(),
+                reason,
+            },
+        );
+    }
+
+    /// Recorded audio and video producer of every participant, see `recorded_producer()`
+    fn participant_media(&self) -> BTreeMap<ParticipantId, ParticipantMedia> {
+        let clients = self.inner.clients.lock();
+
+        clients
+            .iter()
+            .map(|(participant_id, client)| {
+                let producer = |kind| recorded_producer(&client.producers, kind).cloned();
+
+                (
+                    *participant_id,
+                    ParticipantMedia {
+                        audio: producer(MediaKind::Audio),
+                        video: producer(MediaKind::Video),
+                    },
+                )
+            })
+            .collect()
+    }
+
+    fn notify_recording_stopped(
+        &self,
+        participant_id: &ParticipantId,
+        mode: RecordingMode,
+        recording_id: String,
+        result: Result<RecordingSummary, String>,
+    ) {
+        let event = match result {
+            Ok(summary) => {
+                log::info!(
+                    "recording {} stopped: {}",
+                    summary.recording_id,
+                    summary.path.display()
+                );
+                RecordingEvent::Stopped(summary)
+            }
+            Err(reason) => RecordingEvent::Failed {
+                recording_id,
+                reason,
+            },
+        };
+
+        self.call_recording_handlers(participant_id, mode, &event);
+    }
+
+    /// Make sure no other recording uses the same ID, it is released by the event that
+    /// finishes the recording
+    fn reserve_recording_id(&self, recording_id: &str) -> Result<(), String> {
+        self.inner.recording_ids.reserve(recording_id)
+    }
+
+    fn release_recording_id(&self, recording_id: &str) {
+        self.inner.recording_ids.release(recording_id);
+    }
+
+    fn call_recording_handlers(
+        &self,
+        participant_id: &ParticipantId,
+        mode: RecordingMode,
+        event: &RecordingEvent,
+    ) {
+        let finished = match event {
+            RecordingEvent::Started { .. } => None,
+            RecordingEvent::Stopped(summary) => Some(&summary.recording_id),
+            RecordingEvent::Failed { recording_id, .. } => Some(recording_id),
+        };
+        if let Some(recording_id) = finished {
+            self.release_recording_id(recording_id);
+        }
+
+        self.inner
+            .handlers
+            .recording
+            .call_simple(participant_id, &mode, event);
     }
 }
 
@@ -292,35 +1355,252 @@ impl WeakRoom {
     }
 }
 
-/// List of codecs that SFU will accept from clients
-pub fn media_codecs() -> Vec<RtpCodecCapability> {
-    vec![
-        RtpCodecCapability::Audio {
-            mime_type: MimeTypeAudio::Opus,
-            preferred_payload_type: Some(111),
-            clock_rate: NonZeroU32::new(48000).unwrap(),
-            channels: NonZeroU8::new(2).unwrap(),
-            // parameters: RtpCodecParametersParameters::default(),
-            parameters: RtpCodecParametersParameters::from([
-                ("minptime", 10_u32.into()),
-                ("useinbandfec", 1_u32.into()),
-            ]),
-            rtcp_feedback: vec![
-                // RtcpFeedback::TransportCc
-            ],
-        },
-        RtpCodecCapability::Video {
-            mime_type: MimeTypeVideo::H264,
-            preferred_payload_type: Some(125),
-            clock_rate: NonZeroU32::new(90000).unwrap(),
-            parameters: RtpCodecParametersParameters::default(),
-            rtcp_feedback: vec![
-                // RtcpFeedback::Nack,
-                // RtcpFeedback::NackPli,
-                // RtcpFeedback::CcmFir,
-                // RtcpFeedback::GoogRemb,
-                // RtcpFeedback::TransportCc,
-            ],
-        },
-    ]
+/// What the producer captures, set by the client when producing so that screen shares can be
+/// told apart from the camera. It is stored in producer's app data.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ProducerSource {
+    Camera,
+    Microphone,
+    Screen,
+    ScreenAudio,
+}
+
+impl ProducerSource {
+    /// Source assumed for producers the client didn't specify source for
+    pub fn default_for(kind: MediaKind) -> Self {
+        match kind {
+            MediaKind::Audio => Self::Microphone,
+            MediaKind::Video => Self::Camera,
+        }
+    }
+
+    pub fn kind(self) -> MediaKind {
+        match self {
+            Self::Microphone | Self::ScreenAudio => MediaKind::Audio,
+            Self::Camera | Self::Screen => MediaKind::Video,
+        }
+    }
+
+    /// Source the producer was created with
+    pub fn of(producer: &Producer) -> Self {
+        producer
+            .app_data()
+            .downcast_ref::<Self>()
+            .copied()
+            .unwrap_or_else(|| Self::default_for(producer.kind()))
+    }
+}
+
+/// Producer of given kind that is recorded for the participant. Screen share is preferred over
+/// the camera since it is usually what is being presented, while microphone is preferred over
+/// the audio of the screen share so that the speaker is always heard.
+fn recorded_producer(producers: &[Producer], kind: MediaKind) -> Option<&Producer> {
+    let sources = match kind {
+        MediaKind::Audio => [ProducerSource::Microphone, ProducerSource::ScreenAudio],
+        MediaKind::Video => [ProducerSource::Screen, ProducerSource::Camera],
+    };
+
+    sources.into_iter().find_map(|source| {
+        producers
+            .iter()
+            .find(|producer| ProducerSource::of(producer) == source)
+    })
+}
+
+/// Codec that can be enabled in the router config, AV1 is not available since mediasoup 0.11
+/// doesn't support it
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Codec {
+    Opus,
+    Vp8,
+    /// VP9 profile 0 (8 bit 4:2:0)
+    Vp9,
+    /// VP9 profile 2 (10 bit 4:2:0)
+    Vp9Profile2,
+    /// H264 constrained baseline, level 3.1
+    H264,
+    /// H264 main, level 5.0
+    H264Main,
+    /// H264 high, level 5.0
+    H264High,
+}
+
+/// RTCP feedback mechanism that can be enabled in the router config
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Feedback {
+    /// Retransmission of lost packets (video only)
+    Nack,
+    /// Key frame requests with PLI (video only)
+    Pli,
+    /// Key frame requests with FIR (video only)
+    Fir,
+    /// Bandwidth estimation with REMB (video only)
+    Remb,
+    /// Bandwidth estimation with transport-wide congestion control
+    TransportCc,
+}
+
+impl Feedback {
+    fn rtcp_feedback(self, kind: MediaKind) -> Option<RtcpFeedback> {
+        match (self, kind) {
+            (Self::TransportCc, _) => Some(RtcpFeedback::TransportCc),
+            (_, MediaKind::Audio) => None,
+            (Self::Nack, MediaKind::Video) => Some(RtcpFeedback::Nack),
+            (Self::Pli, MediaKind::Video) => Some(RtcpFeedback::NackPli),
+            (Self::Fir, MediaKind::Video) => Some(RtcpFeedback::CcmFir),
+            (Self::Remb, MediaKind::Video) => Some(RtcpFeedback::GoogRemb),
+        }
+    }
+}
+
+impl FromStr for Feedback {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "nack" => Ok(Self::Nack),
+            "pli" => Ok(Self::Pli),
+            "fir" => Ok(Self::Fir),
+            "remb" => Ok(Self::Remb),
+            "transport-cc" => Ok(Self::TransportCc),
+            _ => Err(format!("Unknown RTCP feedback: {s}")),
+        }
+    }
+}
+
+impl TryFrom<String> for Feedback {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl Codec {
+    pub fn kind(self) -> MediaKind {
+        match self {
+            Self::Opus => MediaKind::Audio,
+            _ => MediaKind::Video,
+        }
+    }
+
+    /// Capability offered to clients, payload types are fixed so that they don't depend on the
+    /// order in the config
+    pub fn capability(self, feedback: &[Feedback]) -> RtpCodecCapability {
+        let rtcp_feedback = feedback
+            .iter()
+            .filter_map(|feedback| feedback.rtcp_feedback(self.kind()))
+            .collect();
+
+        match self {
+            Self::Opus => RtpCodecCapability::Audio {
+                mime_type: MimeTypeAudio::Opus,
+                preferred_payload_type: Some(111),
+                clock_rate: NonZeroU32::new(48000).unwrap(),
+                channels: NonZeroU8::new(2).unwrap(),
+                // parameters: RtpCodecParametersParameters::default(),
+                parameters: RtpCodecParametersParameters::from([
+                    ("minptime", 10_u32.into()),
+                    ("useinbandfec", 1_u32.into()),
+                ]),
+                rtcp_feedback,
+            },
+            Self::Vp8 => video_codec(
+                MimeTypeVideo::Vp8,
+                96,
+                RtpCodecParametersParameters::default(),
+                rtcp_feedback,
+            ),
+            Self::Vp9 => video_codec(
+                MimeTypeVideo::Vp9,
+                98,
+                RtpCodecParametersParameters::from([("profile-id", 0_u32.into())]),
+                rtcp_feedback,
+            ),
+            Self::Vp9Profile2 => video_codec(
+                MimeTypeVideo::Vp9,
+                100,
+                RtpCodecParametersParameters::from([("profile-id", 2_u32.into())]),
+                rtcp_feedback,
+            ),
+            Self::H264 => video_codec(
+                MimeTypeVideo::H264,
+                125,
+                h264_parameters("42e01f"),
+                rtcp_feedback,
+            ),
+            Self::H264Main => video_codec(
+                MimeTypeVideo::H264,
+                123,
+                h264_parameters("4d0032"),
+                rtcp_feedback,
+            ),
+            Self::H264High => video_codec(
+                MimeTypeVideo::H264,
+                121,
+                h264_parameters("640032"),
+                rtcp_feedback,
+            ),
+        }
+    }
+}
+
+impl FromStr for Codec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "opus" => Ok(Self::Opus),
+            "vp8" => Ok(Self::Vp8),
+            "vp9" | "vp9-profile0" => Ok(Self::Vp9),
+            "vp9-profile2" => Ok(Self::Vp9Profile2),
+            "h264" | "h264-baseline" => Ok(Self::H264),
+            "h264-main" => Ok(Self::H264Main),
+            "h264-high" => Ok(Self::H264High),
+            "av1" => Err("AV1 is not supported by the mediasoup version in use".to_owned()),
+            _ => Err(format!("Unknown codec: {s}")),
+        }
+    }
+}
+
+impl TryFrom<String> for Codec {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+fn video_codec(
+    mime_type: MimeTypeVideo,
+    preferred_payload_type: u8,
+    parameters: RtpCodecParametersParameters,
+    rtcp_feedback: Vec<RtcpFeedback>,
+) -> RtpCodecCapability {
+    RtpCodecCapability::Video {
+        mime_type,
+        preferred_payload_type: Some(preferred_payload_type),
+        clock_rate: NonZeroU32::new(90000).unwrap(),
+        parameters,
+        rtcp_feedback,
+    }
+}
+
+fn h264_parameters(profile_level_id: &'static str) -> RtpCodecParametersParameters {
+    RtpCodecParametersParameters::from([
+        ("packetization-mode", 1_u32.into()),
+        ("profile-level-id", profile_level_id.into()),
+        ("level-asymmetry-allowed", 1_u32.into()),
+    ])
+}
+
+/// List of codecs that SFU will accept from clients, in order of preference
+pub fn media_codecs(codecs: &[Codec], feedback: &[Feedback]) -> Vec<RtpCodecCapability> {
+    codecs
+        .iter()
+        .map(|codec| codec.capability(feedback))
+        .collect()
 }
@@ -1,73 +1,113 @@
+use crate::config::Config;
+use crate::recording::port_allocator::PortAllocator;
+use crate::recording::RecordingIds;
 use crate::room::{Room, RoomId, WeakRoom};
+use crate::worker_pool::WorkerPool;
 use async_lock::Mutex;
-use mediasoup::prelude::*;
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 use std::sync::Arc;
 
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Clone)]
 pub struct RoomsRegistry {
     // We store `WeakRoom` instead of full `Room` to avoid cycles and to not prevent rooms from
     // being destroyed when last participant disconnects
     rooms: Arc<Mutex<HashMap<RoomId, WeakRoom>>>,
+    /// Workers on which routers of the rooms are created
+    worker_pool: WorkerPool,
+    /// Recording ports are leased from the same allocator by all the rooms
+    recording_ports: PortAllocator,
+    /// Recording IDs are reserved in the same set by all the rooms, recordings of all the rooms
+    /// are stored in the same directory
+    recording_ids: RecordingIds,
+    /// Server configuration shared with all the rooms
+    config: Arc<Config>,
 }
 
 impl RoomsRegistry {
+    /// Create empty registry, rooms created by it will use the given worker pool and
+    /// configuration
+    pub fn new(worker_pool: WorkerPool, config: Arc<Config>) -> Self {
+        Self {
+            rooms: Arc::default(),
+            worker_pool,
+            recording_ports: PortAllocator::new(config.recording.port_range()),
+            recording_ids: RecordingIds::default(),
+            config,
+        }
+    }
+
     /// Retrieves existing room or creates a new one with specified `RoomId`
-    pub async fn get_or_create_room(
-        &self,
-        worker_manager: &WorkerManager,
-        room_id: RoomId,
-    ) -> Result<Room, String> {
+    pub async fn get_or_create_room(&self, room_id: RoomId) -> Result<Room, String> {
         let mut rooms = self.rooms.lock().await;
         match rooms.entry(room_id) {
-            Entry::Occupied(mut entry) => match entry.get().upgrade() {
-                Some(room) => Ok(room),
-                None => {
-                    let room = Room::new_with_id(worker_manager, room_id).await?;
-                    entry.insert(room.downgrade());
-                    room.on_close({
-                        let room_id = room.id();
-                        let rooms = Arc::clone(&self.rooms);
-
-                        move || {
-                            std::thread::spawn(move || {
-                                futures_lite::future::block_on(async move {
-                                    rooms.lock().await.remove(&room_id);
-                                });
-                            });
-                        }
-                    })
-                    .detach();
-                    Ok(room)
+            // Room whose worker has died is replaced with a new one with the same ID
+            Entry::Occupied(mut entry) => {
+                match entry.get().upgrade().filter(|room| !room.is_closed()) {
+                    Some(room) => Ok(room),
+                    None => {
+                        let room = Room::new_with_id(
+                            &self.worker_pool,
+                            Arc::clone(&self.config),
+                            self.recording_ports.clone(),
+                            self.recording_ids.clone(),
+                            room_id,
+                        )
+                        .await?;
+                        entry.insert(room.downgrade());
+                        self.remove_on_close(&room);
+                        Ok(room)
+                    }
                 }
-            },
+            }
             Entry::Vacant(entry) => {
-                let room = Room::new_with_id(worker_manager, room_id).await?;
+                let room = Room::new_with_id(
+                    &self.worker_pool,
+                    Arc::clone(&self.config),
+                    self.recording_ports.clone(),
+                    self.recording_ids.clone(),
+                    room_id,
+                )
+                .await?;
                 entry.insert(room.downgrade());
-                room.on_close({
-                    let room_id = room.id();
-                    let rooms = Arc::clone(&self.rooms);
-
-                    move || {
-                        std::thread::spawn(move || {
-                            futures_lite::future::block_on(async move {
-                                rooms.lock().await.remove(&room_id);
-                            });
-                        });
-                    }
-                })
-                .detach();
+                self.remove_on_close(&room);
                 Ok(room)
             }
         }
     }
 
+    /// Get room with specified `RoomId` if it is still active
+    pub async fn get_room(&self, room_id: &RoomId) -> Option<Room> {
+        self.rooms.lock().await.get(room_id)?.upgrade()
+    }
+
+    /// Get all active rooms
+    pub async fn get_rooms(&self) -> Vec<Room> {
+        self.rooms
+            .lock()
+            .await
+            .values()
+            .filter_map(WeakRoom::upgrade)
+            .collect()
+    }
+
     /// Create new room with random `RoomId`
-    pub async fn create_room(&self, worker_manager: &WorkerManager) -> Result<Room, String> {
+    pub async fn create_room(&self) -> Result<Room, String> {
         let mut rooms = self.rooms.lock().await;
-        let room = Room::new(worker_manager).await?;
+        let room = Room::new(
+            &self.worker_pool,
+            Arc::clone(&self.config),
+            self.recording_ports.clone(),
+            self.recording_ids.clone(),
+        )
+        .await?;
         rooms.insert(room.id(), room.downgrade());
+        self.remove_on_close(&room);
+        Ok(room)
+    }
+
+    /// Remove room from the registry once it is closed
+    fn remove_on_close(&self, room: &Room) {
         room.on_close({
             let room_id = room.id();
             let rooms = Arc::clone(&self.rooms);
@@ -75,12 +115,17 @@ impl RoomsRegistry {
             move || {
                 std::thread::spawn(move || {
                     futures_lite::future::block_on(async move {
-                        rooms.lock().await.remove(&room_id);
+                        let mut rooms = rooms.lock().await;
+                        // The entry may already point to a room that replaced this one
+                        if let Entry::Occupied(entry) = rooms.entry(room_id) {
+                            if entry.get().upgrade().is_none() {
+                                entry.remove();
+                            }
+                        }
                     });
                 });
             }
         })
         .detach();
-        Ok(room)
     }
 }
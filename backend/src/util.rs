@@ -1,15 +1,18 @@
 use std::str::FromStr;
 
-pub fn get_env<T: FromStr>(name: &str) -> Option<T>
+/// Read and parse environment variable, returns `Ok(None)` if it is not set
+pub fn get_env<T: FromStr>(name: &str) -> Result<Option<T>, String>
 where
     T::Err: std::fmt::Debug,
 {
     let v = std::env::var(name);
     let Ok(v) = v else {
-        return None;
+        return Ok(None);
     };
 
-    Some(v.parse::<T>().expect(&format!("{} is invalid.", name)))
+    v.parse::<T>()
+        .map(Some)
+        .map_err(|error| format!("{} is invalid: {:?}", name, error))
 }
 
 pub fn get_env_or<T: FromStr>(name: &str, default_value: T) -> T
@@ -0,0 +1,174 @@
+use crate::config::WorkerConfig;
+use async_lock::Mutex;
+use mediasoup::prelude::*;
+use mediasoup::router::NewTransport;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Number of routers and consumers living on a worker, used to pick the least loaded one
+#[derive(Debug, Default)]
+struct Load {
+    routers: AtomicUsize,
+    consumers: AtomicUsize,
+}
+
+impl Load {
+    fn get(&self) -> (usize, usize) {
+        (
+            self.consumers.load(Ordering::Relaxed),
+            self.routers.load(Ordering::Relaxed),
+        )
+    }
+}
+
+#[derive(Debug)]
+struct PooledWorker {
+    worker: Worker,
+    load: Arc<Load>,
+}
+
+/// Pool of mediasoup workers shared by all the rooms, each new room gets a router on the least
+/// loaded worker
+#[derive(Clone)]
+pub struct WorkerPool {
+    worker_manager: WorkerManager,
+    config: WorkerConfig,
+    workers: Arc<Mutex<Vec<PooledWorker>>>,
+}
+
+impl fmt::Debug for WorkerPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WorkerPool")
+            .field("config", &self.config)
+            .field("workers", &self.workers)
+            .finish()
+    }
+}
+
+impl WorkerPool {
+    /// Create pool and spawn all of its workers right away
+    pub async fn new(worker_manager: WorkerManager, config: WorkerConfig) -> Result<Self, String> {
+        let pool = Self {
+            worker_manager,
+            config,
+            workers: Arc::default(),
+        };
+
+        pool.fill(&mut *pool.workers.lock().await).await?;
+
+        Ok(pool)
+    }
+
+    /// Create router on the least loaded worker
+    pub async fn create_router(&self, options: RouterOptions) -> Result<Router, String> {
+        let mut workers = self.workers.lock().await;
+
+        // Workers that died are replaced by the new ones, rooms that were using them have been
+        // closed already
+        workers.retain(|pooled| !pooled.worker.closed());
+        self.fill(&mut workers).await?;
+
+        let pooled = workers
+            .iter()
+            .min_by_key(|pooled| pooled.load.get())
+            .ok_or_else(|| "Worker pool is empty".to_owned())?;
+
+        let router = pooled
+            .worker
+            .create_router(options)
+            .await
+            .map_err(|error| format!("Failed to create router: {error}"))?;
+
+        pooled.load.routers.fetch_add(1, Ordering::Relaxed);
+        router
+            .on_close({
+                let load = Arc::clone(&pooled.load);
+
+                move || {
+                    load.routers.fetch_sub(1, Ordering::Relaxed);
+                }
+            })
+            .detach();
+
+        // Count consumers of all the transports created on this router
+        router
+            .on_new_transport({
+                let load = Arc::clone(&pooled.load);
+
+                move |transport| {
+                    let on_new_consumer = Arc::new({
+                        let load = Arc::clone(&load);
+
+                        move |consumer: &Consumer| {
+                            load.consumers.fetch_add(1, Ordering::Relaxed);
+                            consumer
+                                .on_close({
+                                    let load = Arc::clone(&load);
+
+                                    move || {
+                                        load.consumers.fetch_sub(1, Ordering::Relaxed);
+                                    }
+                                })
+                                .detach();
+                        }
+                    });
+
+                    match transport {
+                        NewTransport::Direct(transport) => {
+                            transport.on_new_consumer(on_new_consumer).detach()
+                        }
+                        NewTransport::Pipe(transport) => {
+                            transport.on_new_consumer(on_new_consumer).detach()
+                        }
+                        NewTransport::Plain(transport) => {
+                            transport.on_new_consumer(on_new_consumer).detach()
+                        }
+                        NewTransport::WebRtc(transport) => {
+                            transport.on_new_consumer(on_new_consumer).detach()
+                        }
+                    }
+                }
+            })
+            .detach();
+
+        log::debug!(
+            "router {} created on worker {}, load {:?}",
+            router.id(),
+            pooled.worker.id(),
+            pooled.load
+        );
+
+        Ok(router)
+    }
+
+    /// Spawn workers until the pool has the configured size
+    async fn fill(&self, workers: &mut Vec<PooledWorker>) -> Result<(), String> {
+        while workers.len() < self.config.num_workers() {
+            let worker = self
+                .worker_manager
+                .create_worker(self.config.worker_settings())
+                .await
+                .map_err(|error| format!("Failed to create worker: {error}"))?;
+
+            worker
+                .on_dead({
+                    let worker_id = worker.id();
+
+                    move |result| {
+                        log::error!("Worker {worker_id} died: {result:?}");
+                    }
+                })
+                .detach();
+
+            log::info!("Worker {} created", worker.id());
+
+            workers.push(PooledWorker {
+                worker,
+                load: Arc::default(),
+            });
+        }
+
+        Ok(())
+    }
+}
@@ -1,8 +1,22 @@
 /* eslint-disable no-console */
 import { Device } from 'mediasoup-client';
-import type { MediaKind, RtpCapabilities, RtpParameters } from 'mediasoup-client/lib/RtpParameters';
-import type { DtlsParameters, TransportOptions, Transport } from 'mediasoup-client/lib/Transport';
-import type { ConsumerOptions } from 'mediasoup-client/lib/Consumer';
+import type {
+  MediaKind,
+  RtpCapabilities,
+  RtpEncodingParameters,
+  RtpParameters,
+} from 'mediasoup-client/lib/RtpParameters';
+import type {
+  DtlsParameters,
+  IceParameters,
+  TransportOptions,
+  Transport,
+} from 'mediasoup-client/lib/Transport';
+import type { Consumer, ConsumerOptions } from 'mediasoup-client/lib/Consumer';
+import type { Producer } from 'mediasoup-client/lib/Producer';
+import type { DataProducer } from 'mediasoup-client/lib/DataProducer';
+import type { DataConsumer, DataConsumerOptions } from 'mediasoup-client/lib/DataConsumer';
+import type { SctpStreamParameters } from 'mediasoup-client/lib/SctpParameters';
 
 type Brand<K, T> = K & { __brand: T };
 
@@ -10,12 +24,28 @@ type RoomId = Brand<string, 'RoomId'>;
 type ParticipantId = Brand<string, 'ParticipantId'>;
 type ConsumerId = Brand<string, 'ConsumerId'>;
 type ProducerId = Brand<string, 'ProducerId'>;
+type RequestId = Brand<string, 'RequestId'>;
+type SessionToken = Brand<string, 'SessionToken'>;
+type TransportDirection = 'producer' | 'consumer';
+type ProducerSource = 'camera' | 'microphone' | 'screen' | 'screen-audio';
+type DataProducerId = Brand<string, 'DataProducerId'>;
+type DataConsumerId = Brand<string, 'DataConsumerId'>;
+
+// Label of the data channel chat messages are sent over
+const CHAT_LABEL = 'chat';
 
 interface ServerInit {
   action: 'Init';
   roomId: RoomId;
-  consumerTransportOptions: TransportOptions;
-  producerTransportOptions: TransportOptions;
+  participantId: ParticipantId;
+  // Absent when the server doesn't keep sessions of disconnected participants
+  sessionToken?: SessionToken;
+  // Transports, producers and consumers of the previous connection are still in place
+  resumed: boolean;
+  // Only present when requested with `bundleTransports` or when resuming the session,
+  // otherwise transports are created with `CreateTransport`
+  consumerTransportOptions?: TransportOptions;
+  producerTransportOptions?: TransportOptions;
   routerRtpCapabilities: RtpCapabilities;
 }
 
@@ -24,42 +54,280 @@ interface ServerProducerAdded {
   participantId: ParticipantId;
   name: string;
   producerId: ProducerId;
+  source: ProducerSource;
+  paused: boolean;
 }
 
 interface ServerProducerRemoved {
   action: 'ProducerRemoved';
+  requestId?: RequestId;
+  participantId: ParticipantId;
+  producerId: ProducerId;
+}
+
+interface ServerProducerPaused {
+  action: 'ProducerPaused';
+  requestId?: RequestId;
   participantId: ParticipantId;
   producerId: ProducerId;
 }
 
+interface ServerProducerResumed {
+  action: 'ProducerResumed';
+  requestId?: RequestId;
+  participantId: ParticipantId;
+  producerId: ProducerId;
+}
+
+interface ServerTransportCreated {
+  action: 'TransportCreated';
+  requestId?: RequestId;
+  direction: TransportDirection;
+  transportOptions: TransportOptions;
+}
+
 interface ServerConnectedProducerTransport {
   action: 'ConnectedProducerTransport';
+  requestId?: RequestId;
+}
+
+interface ServerIceRestarted {
+  action: 'IceRestarted';
+  requestId?: RequestId;
+  transportId: string;
+  iceParameters: IceParameters;
+}
+
+interface ServerTransportDisconnected {
+  action: 'TransportDisconnected';
+  transportId: string;
+  reason: string;
 }
 
 interface ServerProduced {
   action: 'Produced';
+  requestId?: RequestId;
   id: ProducerId;
 }
 
 interface ServerConnectedConsumerTransport {
   action: 'ConnectedConsumerTransport';
+  requestId?: RequestId;
 }
 
 interface ServerConsumed {
   action: 'Consumed';
+  requestId?: RequestId;
   id: ConsumerId;
+  producerId: ProducerId;
   kind: MediaKind;
   rtpParameters: RtpParameters;
 }
 
+interface ProducerScore {
+  encodingIdx: number;
+  ssrc: number;
+  rid?: string;
+  // From 0 (worst) to 10 (best)
+  score: number;
+}
+
+interface ConsumerScore {
+  score: number;
+  producerScore: number;
+  producerScores: number[];
+}
+
+interface ServerProducerScore {
+  action: 'ProducerScore';
+  producerId: ProducerId;
+  score: ProducerScore[];
+}
+
+interface ServerConsumerScore {
+  action: 'ConsumerScore';
+  consumerId: ConsumerId;
+  score: ConsumerScore;
+}
+
+interface ServerStats {
+  action: 'Stats';
+  requestId?: RequestId;
+  // Stats of transports, producers and consumers as reported by mediasoup
+  stats: {
+    transports: { id: string, stats: object[] }[];
+    producers: { id: ProducerId, kind: MediaKind, score: ProducerScore[], stats: object[] }[];
+    consumers: {
+      id: ConsumerId,
+      producerId: ProducerId,
+      kind: MediaKind,
+      score: ConsumerScore | null,
+      stats: object[],
+    }[];
+  };
+}
+
+interface ServerConsumerClosed {
+  action: 'ConsumerClosed';
+  consumerId: ConsumerId;
+}
+
+interface ServerDataProduced {
+  action: 'DataProduced';
+  requestId?: RequestId;
+  id: DataProducerId;
+}
+
+interface ServerDataProducerAdded {
+  action: 'DataProducerAdded';
+  participantId: ParticipantId;
+  name: string;
+  dataProducerId: DataProducerId;
+  label: string;
+  protocol: string;
+}
+
+interface ServerDataProducerRemoved {
+  action: 'DataProducerRemoved';
+  requestId?: RequestId;
+  participantId: ParticipantId;
+  dataProducerId: DataProducerId;
+}
+
+interface ServerDataConsumed {
+  action: 'DataConsumed';
+  requestId?: RequestId;
+  id: DataConsumerId;
+  dataProducerId: DataProducerId;
+  sctpStreamParameters?: SctpStreamParameters;
+  label: string;
+  protocol: string;
+}
+
+interface ServerDataConsumerClosed {
+  action: 'DataConsumerClosed';
+  dataConsumerId: DataConsumerId;
+}
+
+interface ServerActiveSpeaker {
+  action: 'ActiveSpeaker';
+  participantId: ParticipantId;
+  // dBov, from -127 to 0
+  volume: number;
+}
+
+interface ServerSilence {
+  action: 'Silence';
+}
+
+interface ConsumerLayers {
+  spatialLayer: number;
+  temporalLayer?: number;
+}
+
+interface ServerLayersChanged {
+  action: 'LayersChanged';
+  consumerId: ConsumerId;
+  // No layer is forwarded, for instance because producer is paused
+  layers: ConsumerLayers | null;
+}
+
+// `Room` records all participants of the room into a single file
+type RecordingMode = 'Participant' | 'Room';
+
+interface ServerRecordingStarted {
+  action: 'RecordingStarted';
+  requestId?: RequestId;
+  participantId: ParticipantId;
+  mode: RecordingMode;
+  recordingId: string;
+}
+
+interface ServerRecordingStopped {
+  action: 'RecordingStopped';
+  requestId?: RequestId;
+  participantId: ParticipantId;
+  mode: RecordingMode;
+  recordingId: string;
+  // Location of the recorded file on the server
+  path: string;
+  durationMs: number;
+  sizeBytes: number;
+}
+
+interface ServerRecordingFailed {
+  action: 'RecordingFailed';
+  participantId: ParticipantId;
+  mode: RecordingMode;
+  recordingId: string;
+  reason: string;
+}
+
+interface ServerRoomClosed {
+  action: 'RoomClosed';
+  reason: string;
+}
+
+type ErrorCode =
+  'InvalidMessage' |
+  'MissingRtpCapabilities' |
+  'TransportCreateFailed' |
+  'TransportConnectFailed' |
+  'TransportNotFound' |
+  'IceRestartFailed' |
+  'ProduceFailed' |
+  'ConsumeFailed' |
+  'ProduceDataFailed' |
+  'ConsumeDataFailed' |
+  'ConsumerNotFound' |
+  'ConsumerResumeFailed' |
+  'ConsumerPauseFailed' |
+  'ProducerNotFound' |
+  'ProducerPauseFailed' |
+  'DataProducerNotFound' |
+  'DataConsumerNotFound' |
+  'SetPreferredLayersFailed' |
+  'RecordingFailed' |
+  'StatsFailed';
+
+interface ServerError {
+  action: 'Error';
+  requestAction: ClientMessage['action'] | null;
+  requestId?: RequestId;
+  code: ErrorCode;
+  message: string;
+}
+
 type ServerMessage =
   ServerInit |
   ServerProducerAdded |
   ServerProducerRemoved |
+  ServerProducerPaused |
+  ServerProducerResumed |
+  ServerTransportCreated |
   ServerConnectedProducerTransport |
+  ServerIceRestarted |
+  ServerTransportDisconnected |
   ServerProduced |
   ServerConnectedConsumerTransport |
-  ServerConsumed;
+  ServerConsumed |
+  ServerConsumerClosed |
+  ServerDataProduced |
+  ServerDataProducerAdded |
+  ServerDataProducerRemoved |
+  ServerDataConsumed |
+  ServerDataConsumerClosed |
+  ServerProducerScore |
+  ServerConsumerScore |
+  ServerStats |
+  ServerLayersChanged |
+  ServerActiveSpeaker |
+  ServerSilence |
+  ServerRecordingStarted |
+  ServerRecordingStopped |
+  ServerRecordingFailed |
+  ServerRoomClosed |
+  ServerError;
 
 interface ClientInit {
   action: 'Init';
@@ -67,8 +335,15 @@ interface ClientInit {
   rtpCapabilities: RtpCapabilities;
 }
 
+interface ClientCreateTransport {
+  action: 'CreateTransport';
+  direction: TransportDirection;
+}
+
 interface ClientConnectProducerTransport {
   action: 'ConnectProducerTransport';
+  // The first producer transport is used when omitted
+  transportId?: string;
   dtlsParameters: DtlsParameters;
 }
 
@@ -77,10 +352,34 @@ interface ClientConnectConsumerTransport {
   dtlsParameters: DtlsParameters;
 }
 
+interface ClientRestartIce {
+  action: 'RestartIce';
+  transportId: string;
+}
+
 interface ClientProduce {
   action: 'Produce';
+  // The first producer transport is used when omitted
+  transportId?: string;
   kind: MediaKind;
   rtpParameters: RtpParameters;
+  // Camera or microphone when omitted
+  source?: ProducerSource;
+}
+
+interface ClientPauseProducer {
+  action: 'PauseProducer';
+  producerId: ProducerId;
+}
+
+interface ClientResumeProducer {
+  action: 'ResumeProducer';
+  producerId: ProducerId;
+}
+
+interface ClientCloseProducer {
+  action: 'CloseProducer';
+  producerId: ProducerId;
 }
 
 interface ClientConsume {
@@ -88,33 +387,137 @@ interface ClientConsume {
   producerId: ProducerId;
 }
 
+interface ClientProduceData {
+  action: 'ProduceData';
+  // The first producer transport is used when omitted
+  transportId?: string;
+  sctpStreamParameters: SctpStreamParameters;
+  label?: string;
+  protocol?: string;
+}
+
+interface ClientConsumeData {
+  action: 'ConsumeData';
+  dataProducerId: DataProducerId;
+}
+
+interface ClientCloseDataProducer {
+  action: 'CloseDataProducer';
+  dataProducerId: DataProducerId;
+}
+
+interface ClientCloseDataConsumer {
+  action: 'CloseDataConsumer';
+  dataConsumerId: DataConsumerId;
+}
+
 interface ClientConsumerResume {
   action: 'ConsumerResume';
   id: ConsumerId;
 }
 
+interface ClientConsumerPause {
+  action: 'ConsumerPause';
+  id: ConsumerId;
+}
+
+interface ClientConsumerClose {
+  action: 'ConsumerClose';
+  id: ConsumerId;
+}
+
+interface ClientSetPreferredLayers {
+  action: 'SetPreferredLayers';
+  consumerId: ConsumerId;
+  spatialLayer: number;
+  // Highest temporal layer is used when omitted
+  temporalLayer?: number;
+}
+
+interface ClientGetStats {
+  action: 'GetStats';
+}
+
 interface ClientStartRecording {
   action: 'StartRecording';
-  outputName: string;
+  // Server generates the name when omitted
+  outputName?: string;
+  mode?: RecordingMode;
 }
 
 interface ClientStopRecording {
   action: 'StopRecording';
+  mode?: RecordingMode;
 }
 
 type ClientMessage =
   ClientInit |
+  ClientCreateTransport |
   ClientConnectProducerTransport |
+  ClientRestartIce |
   ClientProduce |
+  ClientPauseProducer |
+  ClientResumeProducer |
+  ClientCloseProducer |
   ClientConnectConsumerTransport |
   ClientConsume |
+  ClientProduceData |
+  ClientConsumeData |
+  ClientCloseDataProducer |
+  ClientCloseDataConsumer |
   ClientConsumerResume |
+  ClientConsumerPause |
+  ClientConsumerClose |
+  ClientSetPreferredLayers |
+  ClientGetStats |
   ClientStartRecording |
   ClientStopRecording;
 
+// Every client message may carry a request ID that server echoes back in the response
+type ClientRequest = ClientMessage & { requestId?: RequestId };
+
+// Simulcast encodings of the camera, from the lowest spatial layer to the highest
+const VIDEO_ENCODINGS: RtpEncodingParameters[] = [
+  { scaleResolutionDownBy: 4, maxBitrate: 150_000, scalabilityMode: 'L1T3' },
+  { scaleResolutionDownBy: 2, maxBitrate: 500_000, scalabilityMode: 'L1T3' },
+  { scaleResolutionDownBy: 1, maxBitrate: 1_500_000, scalabilityMode: 'L1T3' },
+];
+
+// Lowest spatial layer that still looks sharp in a tile of given width (in device pixels),
+// assuming 640px wide camera
+const spatialLayerForWidth = (width: number): number => {
+  if (width <= 160) {
+    return 0;
+  } else if (width <= 320) {
+    return 1;
+  } else {
+    return 2;
+  }
+};
+
+// Screen share is shown in its own tile next to the participant's camera
+const isScreenSource = (source: ProducerSource): boolean => {
+  return source === 'screen' || source === 'screen-audio';
+};
+
+const screenTileId = (participantId: ParticipantId): ParticipantId => {
+  return `${participantId}:screen` as ParticipantId;
+};
+
+export type ChatMessage = {
+  name: string;
+  text: string;
+  isSelf: boolean;
+};
+
 export type ParticipantInfo = {
   id: string;
   name: string;
+  isScreen: boolean;
+  isRecording: boolean;
+  isSpeaking: boolean;
+  isMicMuted: boolean;
+  isCameraOff: boolean;
 };
 
 export class Participant {
@@ -123,7 +526,8 @@ export class Participant {
   public readonly id: ParticipantId;
 
   constructor(
-    public readonly id_: ParticipantId
+    public readonly id_: ParticipantId,
+    public readonly isScreen: boolean = false,
   ) {
     this.id = id_;
   }
@@ -146,6 +550,10 @@ export class Participant {
     this.mediaStream.removeTrack(track);
   }
 
+  public hasTrack(track: MediaStreamTrack): boolean {
+    return this.mediaStream.getTrackById(track.id) !== null;
+  }
+
   public hasTracks(): boolean {
     return this.mediaStream.getTracks().length > 0;
   }
@@ -158,11 +566,42 @@ export class Participant {
 export class VideoChatManager {
   private participants = new Map<ParticipantId, Participant>();
   private producerIdToTrack = new Map<ProducerId, MediaStreamTrack>();
+  // Tiles the tracks of the producers are shown in, either the participant's or their screen's
+  private producerIdToTile = new Map<ProducerId, ParticipantId>();
   private selfTracks: MediaStreamTrack[] = [];
+  // Own producers, paused while microphone or camera is disabled
+  private selfProducers: Producer[] = [];
+  // Own screen share producers, empty while the screen is not shared
+  private screenProducers: Producer[] = [];
+  private _shareScreen: () => Promise<Producer[]> = async () => [];
+  // Data channel own chat messages are sent over, and messages sent and received so far
+  private chatProducer: DataProducer | undefined;
+  private chatMessages: ChatMessage[] = [];
+  // Producers of other participants that are paused
+  private pausedProducers = new Set<ProducerId>();
+  private selfId: ParticipantId | undefined;
+  // Recording IDs of the participants (including self) whose recording is in progress
+  private recordings = new Map<ParticipantId, string>();
+  // Recording ID of the whole room recording in progress
+  private roomRecording: string | undefined;
+  // Video consumers of the participants with the spatial layer requested for them and whether
+  // they were paused because the tile is off-screen
+  private videoConsumers = new Map<ParticipantId, {
+    id: ConsumerId,
+    producerId: ProducerId,
+    spatialLayer?: number,
+    paused: boolean,
+  }>();
+  // Width of the tiles the participants are shown in, in device pixels
+  private tileWidths = new Map<ParticipantId, number>();
+  // Participants whose tiles are scrolled out of view
+  private hiddenTiles = new Set<ParticipantId>();
+  // Loudest participant (possibly self), undefined while nobody is speaking
+  private activeSpeaker: ParticipantId | undefined;
   private updateTrigger: () => void;
   private _isCameraEnabled = true;
   private _isMicEnabled = true;
-  private _send: (msg: ClientMessage) => void = (_) => {};
+  private _send: (msg: ClientRequest) => void = (_) => {};
 
   public constructor(updateTriggerFunc: () => void) {
     this.updateTrigger = updateTriggerFunc;
@@ -172,47 +611,245 @@ export class VideoChatManager {
     this.selfTracks.push(track);
   }
 
-  public setSendFunction(func: (msg: ClientMessage) => void): void {
+  public addSelfProducer(producer: Producer): void {
+    this.selfProducers.push(producer);
+  }
+
+  public setSendFunction(func: (msg: ClientRequest) => void): void {
     this._send = func;
   }
 
-  public send(msg: ClientMessage): void {
+  public setShareScreenFunction(func: () => Promise<Producer[]>): void {
+    this._shareScreen = func;
+  }
+
+  public setChatProducer(dataProducer: DataProducer): void {
+    this.chatProducer = dataProducer;
+  }
+
+  public sendChatMessage(text: string): void {
+    if (!this.chatProducer || this.chatProducer.readyState !== 'open') {
+      console.error('Chat is not available');
+      return;
+    }
+    this.chatProducer.send(text);
+    this.chatMessages.push({ name: 'You', text, isSelf: true });
+    this.updateTrigger();
+  }
+
+  public addChatMessage(name: string, text: string): void {
+    this.chatMessages.push({ name, text, isSelf: false });
+    this.updateTrigger();
+  }
+
+  getChatMessages(): ChatMessage[] {
+    return [...this.chatMessages];
+  }
+
+  isScreenSharing(): boolean {
+    return this.screenProducers.length > 0;
+  }
+
+  public async startScreenShare(): Promise<void> {
+    if (this.isScreenSharing()) {
+      return;
+    }
+    this.screenProducers = await this._shareScreen();
+    for (const producer of this.screenProducers) {
+      // Sharing can also be stopped from the browser UI
+      producer.track?.addEventListener('ended', () => {
+        this.stopScreenShare();
+      });
+    }
+    this.updateTrigger();
+  }
+
+  public stopScreenShare(): void {
+    for (const producer of this.screenProducers) {
+      producer.close();
+      this.send({
+        action: 'CloseProducer',
+        producerId: producer.id as ProducerId,
+      });
+    }
+    this.screenProducers = [];
+    this.updateTrigger();
+  }
+
+  public send(msg: ClientRequest): void {
     this._send(msg);
   }
 
+  public setSelfId(id: ParticipantId): void {
+    this.selfId = id;
+  }
+
+  public setRecording(
+    participantId: ParticipantId,
+    mode: RecordingMode,
+    recordingId: string | undefined,
+  ): void {
+    if (mode === 'Room') {
+      this.roomRecording = recordingId;
+    } else if (recordingId === undefined) {
+      this.recordings.delete(participantId);
+    } else {
+      this.recordings.set(participantId, recordingId);
+    }
+    this.updateTrigger();
+  }
+
+  isRecording(): boolean {
+    return this.selfId !== undefined && this.recordings.has(this.selfId);
+  }
+
+  isRoomRecording(): boolean {
+    return this.roomRecording !== undefined;
+  }
+
+  public setActiveSpeaker(participantId: ParticipantId | undefined): void {
+    if (this.activeSpeaker === participantId) {
+      return;
+    }
+    this.activeSpeaker = participantId;
+    this.updateTrigger();
+  }
+
+  isSpeaking(): boolean {
+    return this.selfId !== undefined && this.activeSpeaker === this.selfId;
+  }
+
   public addTrack(
     participantId: ParticipantId,
     name: string,
     producerId: ProducerId,
+    source: ProducerSource,
     track: MediaStreamTrack,
   ): void {
+    const isScreen = isScreenSource(source);
+    const tileId = isScreen ? screenTileId(participantId) : participantId;
     this.producerIdToTrack.set(producerId, track);
-    const p = this.getOrCreateParticipant(participantId);
+    this.producerIdToTile.set(producerId, tileId);
+    const p = this.getOrCreateParticipant(tileId, isScreen);
     p.name = name;
     p.addTrack(track);
     this.updateTrigger();
   }
 
+  public addVideoConsumer(
+    participantId: ParticipantId,
+    producerId: ProducerId,
+    consumerId: ConsumerId,
+  ): void {
+    const tileId = this.producerIdToTile.get(producerId) ?? participantId;
+    this.videoConsumers.set(tileId, { id: consumerId, producerId, paused: false });
+    this.updatePreferredLayers(tileId);
+    this.updateConsumerPaused(tileId);
+  }
+
+  // Called when the tile of the participant enters or leaves the viewport, video of the tiles
+  // that can't be seen is not received at all
+  public setTileVisible(id: string, visible: boolean): void {
+    if (visible) {
+      this.hiddenTiles.delete(id as ParticipantId);
+    } else {
+      this.hiddenTiles.add(id as ParticipantId);
+    }
+    this.updateConsumerPaused(id as ParticipantId);
+  }
+
+  private updateConsumerPaused(participantId: ParticipantId): void {
+    const consumer = this.videoConsumers.get(participantId);
+    if (consumer === undefined) {
+      return;
+    }
+
+    const paused = this.hiddenTiles.has(participantId);
+    if (consumer.paused === paused) {
+      return;
+    }
+
+    consumer.paused = paused;
+    this.send({
+      action: paused ? 'ConsumerPause' : 'ConsumerResume',
+      id: consumer.id,
+    });
+  }
+
+  // Called when the tile of the participant is resized, so that small tiles receive lower
+  // simulcast layers and don't waste bandwidth
+  public setTileWidth(id: string, width: number): void {
+    this.tileWidths.set(id as ParticipantId, width);
+    this.updatePreferredLayers(id as ParticipantId);
+  }
+
+  private updatePreferredLayers(participantId: ParticipantId): void {
+    const consumer = this.videoConsumers.get(participantId);
+    const width = this.tileWidths.get(participantId);
+    if (consumer === undefined || width === undefined) {
+      return;
+    }
+
+    const spatialLayer = spatialLayerForWidth(width);
+    if (consumer.spatialLayer === spatialLayer) {
+      return;
+    }
+
+    consumer.spatialLayer = spatialLayer;
+    this.send({
+      action: 'SetPreferredLayers',
+      consumerId: consumer.id,
+      spatialLayer,
+    });
+  }
+
+  public setProducerPaused(producerId: ProducerId, paused: boolean): void {
+    if (paused) {
+      this.pausedProducers.add(producerId);
+    } else {
+      this.pausedProducers.delete(producerId);
+    }
+    this.updateTrigger();
+  }
+
+  // Whether the participant has a track of given kind whose producer is paused
+  private hasPausedTrack(participant: Participant, kind: string): boolean {
+    return [...this.pausedProducers].some((producerId) => {
+      const track = this.producerIdToTrack.get(producerId);
+      return track !== undefined && track.kind === kind && participant.hasTrack(track);
+    });
+  }
+
   public deleteTrack(participantId: ParticipantId, producerId: ProducerId) {
     const track = this.producerIdToTrack.get(producerId);
+    const tileId = this.producerIdToTile.get(producerId) ?? participantId;
+    this.producerIdToTrack.delete(producerId);
+    this.producerIdToTile.delete(producerId);
+    this.pausedProducers.delete(producerId);
+
+    if (this.videoConsumers.get(tileId)?.producerId === producerId) {
+      this.videoConsumers.delete(tileId);
+    }
 
     if (track) {
-      const participant = this.getParticipant(participantId);
+      const participant = this.getParticipant(tileId);
       if (participant !== undefined) {
         participant.deleteTrack(track);
         if (!participant.hasTracks()) {
-          this.participants.delete(participantId);
+          this.participants.delete(tileId);
+          this.tileWidths.delete(tileId);
+          this.hiddenTiles.delete(tileId);
         }
       }
     }
     this.updateTrigger();
   }
 
-  getOrCreateParticipant(id: ParticipantId): Participant {
+  getOrCreateParticipant(id: ParticipantId, isScreen: boolean = false): Participant {
     let participant = this.participants.get(id);
 
     if (!participant) {
-      participant = new Participant(id);
+      participant = new Participant(id, isScreen);
       this.participants.set(id, participant);
     }
 
@@ -228,6 +865,11 @@ export class VideoChatManager {
       return {
         id,
         name: data.name,
+        isScreen: data.isScreen,
+        isRecording: this.recordings.has(id),
+        isSpeaking: this.activeSpeaker === id,
+        isMicMuted: this.hasPausedTrack(data, 'audio'),
+        isCameraOff: this.hasPausedTrack(data, 'video'),
       };
     });
   }
@@ -252,6 +894,7 @@ export class VideoChatManager {
         t.enabled = flag;
       }
     }
+    this.setSelfProducersPaused("video", !flag);
   }
 
   isMicEnabled(): boolean {
@@ -265,18 +908,39 @@ export class VideoChatManager {
         t.enabled = flag;
       }
     }
+    this.setSelfProducersPaused("audio", !flag);
   }
 
-  startRecording(outputName: string) {
+  // Pause producers on the server too, so that others stop receiving them and see the state
+  private setSelfProducersPaused(kind: MediaKind, paused: boolean) {
+    for (const producer of this.selfProducers) {
+      if (producer.kind !== kind || producer.closed) {
+        continue;
+      }
+      if (paused) {
+        producer.pause();
+      } else {
+        producer.resume();
+      }
+      this.send({
+        action: paused ? "PauseProducer" : "ResumeProducer",
+        producerId: producer.id as ProducerId,
+      });
+    }
+  }
+
+  startRecording(outputName?: string, mode: RecordingMode = 'Participant') {
     this.send({
       action: "StartRecording",
       outputName,
+      mode,
     });
   }
 
-  stopRecording() {
+  stopRecording(mode: RecordingMode = 'Participant') {
     this.send({
       action: "StopRecording",
+      mode,
     });
   }
 }
@@ -291,6 +955,9 @@ const getIceServers = (): RTCIceServer[] => {
 
 let shouldUseTurnServer: boolean = false;
 
+// Delay before reconnecting to resume the session after WebSocket connection was lost
+const RECONNECT_DELAY_MS = 1000;
+
 export async function init(
   name: string,
   mgr: VideoChatManager,
@@ -307,23 +974,161 @@ export async function init(
     wsUrl.searchParams.set('roomId', roomId);
   }
 
-  const ws = new WebSocket(wsUrl.toString());
+  let ws: WebSocket;
+  let sessionToken: SessionToken | undefined;
 
-  mgr.setSendFunction((message: ClientMessage) => {
+  mgr.setSendFunction((message: ClientRequest) => {
     ws.send(JSON.stringify(message));
   });
 
   const device = new Device();
   let producerTransport: Transport | undefined;
   let consumerTransport: Transport | undefined;
+  // Consumers with the participant and producer they receive
+  const consumers = new Map<ConsumerId, {
+    consumer: Consumer,
+    participantId: ParticipantId,
+    producerId: ProducerId,
+  }>();
+  const dataConsumers = new Map<DataConsumerId, DataConsumer>();
 
   let sequentialMessages: Promise<void> = Promise.resolve();
-  const waitingForResponse: Map<ServerMessage['action'], Function> = new Map();
+  let nextRequestId = 0;
+  const waitingForResponse: Map<RequestId, {
+    resolve: (message: ServerMessage) => void,
+    reject: (error: Error) => void,
+  }> = new Map();
+
+  // Send request to the server and wait for the response with the same request ID,
+  // `Error` response rejects the returned promise
+  const request = <T extends ServerMessage>(message: ClientMessage): Promise<T> => {
+    const requestId = `${nextRequestId++}` as RequestId;
+
+    return new Promise((resolve, reject) => {
+      waitingForResponse.set(requestId, {
+        resolve: resolve as (message: ServerMessage) => void,
+        reject,
+      });
+      mgr.send({ ...message, requestId });
+    });
+  };
+
+  // Use transport options sent with `Init` if any, otherwise ask the server to create the
+  // transport, so that only the transports that are actually used take up ports
+  const getTransportOptions = async (
+    direction: TransportDirection,
+    bundledOptions?: TransportOptions,
+  ): Promise<TransportOptions> => {
+    const options = bundledOptions ?? (
+      await request<ServerTransportCreated>({ action: 'CreateTransport', direction })
+    ).transportOptions;
+
+    if (shouldUseTurnServer) {
+      options.iceTransportPolicy = "relay";
+      options.iceServers = getIceServers();
+    }
+    return options;
+  };
+
+  // Producer transports are connected and produce with their own ID, since screen share is sent
+  // over a separate transport
+  const createProducerTransport = async (
+    bundledOptions?: TransportOptions,
+  ): Promise<Transport> => {
+    const transport = device.createSendTransport(
+      await getTransportOptions('producer', bundledOptions)
+    );
+
+    transport
+      .on('connect', ({ dtlsParameters }, success, failure) => {
+        // Send request to establish producer transport connection
+        // and wait for confirmation
+        request<ServerConnectedProducerTransport>({
+          action: 'ConnectProducerTransport',
+          transportId: transport.id,
+          dtlsParameters
+        })
+          .then(() => {
+            success();
+            console.log(`Producer transport ${transport.id} connected`);
+          })
+          .catch(failure);
+      })
+      .on('produce', ({ kind, rtpParameters, appData }, success, failure) => {
+        // Once connection is established, send request to produce
+        // audio or video track
+        request<ServerProduced>({
+          action: 'Produce',
+          transportId: transport.id,
+          kind,
+          rtpParameters,
+          source: appData.source as ProducerSource | undefined,
+        })
+          .then(({ id }) => {
+            success({ id });
+          })
+          .catch(failure);
+      })
+      .on('producedata', ({ sctpStreamParameters, label, protocol }, success, failure) => {
+        request<ServerDataProduced>({
+          action: 'ProduceData',
+          transportId: transport.id,
+          sctpStreamParameters,
+          label,
+          protocol,
+        })
+          .then(({ id }) => {
+            success({ id });
+          })
+          .catch(failure);
+      });
+
+    return transport;
+  };
+
+  // Screen share has its own transport, so that its bandwidth estimation is independent of
+  // the camera, the transport is reused when sharing again
+  let screenTransport: Transport | undefined;
+  mgr.setShareScreenFunction(async () => {
+    const mediaStream = await navigator.mediaDevices.getDisplayMedia({
+      video: true,
+      audio: true,
+    });
+
+    if (!screenTransport) {
+      screenTransport = await createProducerTransport();
+    }
+
+    const producers: Producer[] = [];
+    for (const track of mediaStream.getTracks()) {
+      const producer = await screenTransport.produce({
+        track,
+        appData: { source: track.kind === 'video' ? 'screen' : 'screen-audio' },
+      });
+
+      console.log(`Screen ${track.kind} producer created:`, producer);
+      producers.push(producer);
+    }
+    return producers;
+  });
 
   const onmessage = async (message: ServerMessage) => {
     switch (message.action) {
       case 'Init': {
         console.log("on init");
+        sessionToken = message.sessionToken;
+        wsUrl.searchParams.set('roomId', message.roomId);
+        if (message.resumed) {
+          console.log('Session resumed');
+          break;
+        }
+        if (device.loaded) {
+          // Session has expired, previous transports and producers are gone on the server
+          console.error('Failed to resume session, rejoining the room');
+          location.reload();
+          break;
+        }
+        mgr.setSelfId(message.participantId);
         if (!roomId) {
           const url = new URL(location.href);
 
@@ -344,42 +1149,7 @@ export async function init(
         });
 
         // Producer transport is needed to send audio and video to SFU
-        if (shouldUseTurnServer) {
-          message.producerTransportOptions.iceTransportPolicy = "relay";
-          message.producerTransportOptions.iceServers = getIceServers();
-        }
-        producerTransport = device.createSendTransport(
-          message.producerTransportOptions
-        );
-
-        producerTransport
-          .on('connect', ({ dtlsParameters }, success) => {
-            // Send request to establish producer transport connection
-            mgr.send({
-              action: 'ConnectProducerTransport',
-              dtlsParameters
-            });
-            // And wait for confirmation, but, obviously, no error handling,
-            // which you should definitely have in real-world applications
-            waitingForResponse.set('ConnectedProducerTransport', () => {
-              success();
-              console.log('Producer transport connected');
-            });
-          })
-          .on('produce', ({ kind, rtpParameters }, success) => {
-            // Once connection is established, send request to produce
-            // audio or video track
-            mgr.send({
-              action: 'Produce',
-              kind,
-              rtpParameters
-            });
-            // And wait for confirmation, but, obviously, no error handling,
-            // which you should definitely have in real-world applications
-            waitingForResponse.set('Produced', ({ id }: { id: string }) => {
-              success({ id });
-            });
-          });
+        producerTransport = await createProducerTransport(message.producerTransportOptions);
 
         // Request microphone and camera access, in real-world apps you may want
         // to do this separately so that audio-only and video-only cases are
@@ -393,54 +1163,63 @@ export async function init(
 
         // And create producers for all tracks that were previously requested
         for (const track of mediaStream.getTracks()) {
-          const producer = await producerTransport.produce({ track, zeroRtpOnPause: true });
+          // Camera is sent in several qualities, so that every receiver can get what fits
+          const producer = await producerTransport.produce({
+            track,
+            zeroRtpOnPause: true,
+            encodings: track.kind === 'video' ? VIDEO_ENCODINGS : undefined,
+            appData: { source: track.kind === 'video' ? 'camera' : 'microphone' },
+          });
 
           console.log(`${track.kind} producer created:`, producer);
           mgr.addSelfTrack(track);
+          mgr.addSelfProducer(producer);
         }
 
-        // Producer transport will be needed to receive produced tracks
-        if (shouldUseTurnServer) {
-          message.consumerTransportOptions.iceTransportPolicy = "relay";
-          message.consumerTransportOptions.iceServers = getIceServers();
-        }
+        // Chat messages are sent to everyone in the room over a data channel
+        mgr.setChatProducer(await producerTransport.produceData({ label: CHAT_LABEL }));
+
+        // Consumer transport will be needed to receive produced tracks
         consumerTransport = device.createRecvTransport(
-          message.consumerTransportOptions
+          await getTransportOptions('consumer', message.consumerTransportOptions)
         );
 
         consumerTransport
-          .on('connect', ({ dtlsParameters }, success) => {
+          .on('connect', ({ dtlsParameters }, success, failure) => {
             // Send request to establish consumer transport connection
-            mgr.send({
+            // and wait for confirmation
+            request<ServerConnectedConsumerTransport>({
               action: 'ConnectConsumerTransport',
               dtlsParameters
-            });
-            // And wait for confirmation, but, obviously, no error handling,
-            // which you should definitely have in real-world applications
-            waitingForResponse.set('ConnectedConsumerTransport', () => {
-              success();
-              console.log('Consumer transport connected');
-            });
+            })
+              .then(() => {
+                success();
+                console.log('Consumer transport connected');
+              })
+              .catch(failure);
           });
         break;
       }
       case 'ProducerAdded': {
-        await new Promise((resolve) => {
-          // Send request to consume producer
-          mgr.send({
-            action: 'Consume',
-            producerId: message.producerId
-          });
-          // And wait for confirmation, but, obviously, no error handling,
-          // which you should definitely have in real-world applications
-          waitingForResponse.set('Consumed', async (consumerOptions: ConsumerOptions) => {
+        // Send request to consume producer, requests are matched with responses by
+        // request ID, so there is no need to wait here for other consumers to be created
+        request<ServerConsumed>({
+          action: 'Consume',
+          producerId: message.producerId
+        })
+          .then(async (consumerOptions) => {
             // Once confirmation is received, corresponding consumer
             // can be created client-side
             const consumer = await (consumerTransport as Transport).consume(
-              consumerOptions
+              consumerOptions as ConsumerOptions
             );
 
             console.log(`${consumer.kind} consumer created:`, consumer);
+            consumers.set(consumer.id as ConsumerId, {
+              consumer,
+              participantId: message.participantId,
+              producerId: message.producerId,
+            });
 
             // Consumer needs to be resumed after being created in
             // paused state (see official documentation about why:
@@ -451,10 +1230,50 @@ export async function init(
             });
 
             mgr
-              .addTrack(message.participantId, message.name, message.producerId, consumer.track);
-            resolve(undefined);
+              .addTrack(
+                message.participantId,
+                message.name,
+                message.producerId,
+                message.source,
+                consumer.track,
+              );
+            if (message.paused) {
+              mgr.setProducerPaused(message.producerId, true);
+            }
+            if (consumer.kind === 'video') {
+              mgr.addVideoConsumer(
+                message.participantId,
+                message.producerId,
+                consumer.id as ConsumerId,
+              );
+            }
+          })
+          .catch((error) => {
+            console.error(`Failed to consume producer ${message.producerId}:`, error);
           });
-        });
+        break;
+      }
+      case 'TransportDisconnected': {
+        console.warn(`Transport ${message.transportId} disconnected: ${message.reason}`);
+        const transport = [producerTransport, consumerTransport]
+          .find((transport) => transport?.id === message.transportId);
+        if (!transport) {
+          break;
+        }
+
+        // Network may have changed, get new ICE parameters and reconnect without rejoining
+        request<ServerIceRestarted>({
+          action: 'RestartIce',
+          transportId: transport.id,
+        })
+          .then(({ iceParameters }) => transport.restartIce({ iceParameters }))
+          .then(() => {
+            console.log(`ICE of transport ${transport.id} restarted`);
+          })
+          .catch((error) => {
+            console.error(`Failed to restart ICE of transport ${transport.id}:`, error);
+          });
+
         break;
       }
       case 'ProducerRemoved': {
@@ -463,34 +1282,171 @@ export async function init(
 
         break;
       }
+      case 'ProducerPaused': {
+        mgr.setProducerPaused(message.producerId, true);
+
+        break;
+      }
+      case 'ProducerResumed': {
+        mgr.setProducerPaused(message.producerId, false);
+
+        break;
+      }
+      case 'ProducerScore': {
+        console.log(`Score of producer ${message.producerId}:`, message.score);
+
+        break;
+      }
+      case 'ConsumerScore': {
+        console.log(`Score of consumer ${message.consumerId}:`, message.score);
+
+        break;
+      }
+      case 'ConsumerClosed': {
+        const entry = consumers.get(message.consumerId);
+        if (entry) {
+          consumers.delete(message.consumerId);
+          entry.consumer.close();
+          mgr.deleteTrack(entry.participantId, entry.producerId);
+        }
+
+        break;
+      }
+      case 'DataProducerAdded': {
+        request<ServerDataConsumed>({
+          action: 'ConsumeData',
+          dataProducerId: message.dataProducerId,
+        })
+          .then(async (dataConsumerOptions) => {
+            const dataConsumer = await (consumerTransport as Transport).consumeData(
+              dataConsumerOptions as DataConsumerOptions
+            );
+
+            console.log(`Data consumer created:`, dataConsumer);
+            dataConsumers.set(dataConsumer.id as DataConsumerId, dataConsumer);
+            if (dataConsumer.label === CHAT_LABEL) {
+              dataConsumer.on('message', (data) => {
+                mgr.addChatMessage(message.name, data);
+              });
+            }
+          })
+          .catch((error) => {
+            console.error(`Failed to consume data producer ${message.dataProducerId}:`, error);
+          });
+
+        break;
+      }
+      case 'DataProducerRemoved': {
+        // Data consumer is closed by the server too and `DataConsumerClosed` follows
+        console.log(`Data producer ${message.dataProducerId} removed`);
+
+        break;
+      }
+      case 'DataConsumerClosed': {
+        dataConsumers.get(message.dataConsumerId)?.close();
+        dataConsumers.delete(message.dataConsumerId);
+
+        break;
+      }
+      case 'LayersChanged': {
+        console.log(`Layers of consumer ${message.consumerId} changed:`, message.layers);
+
+        break;
+      }
+      case 'ActiveSpeaker': {
+        mgr.setActiveSpeaker(message.participantId);
+
+        break;
+      }
+      case 'Silence': {
+        mgr.setActiveSpeaker(undefined);
+
+        break;
+      }
+      case 'RecordingStarted': {
+        console.log(`Recording started: ${message.recordingId}`);
+        mgr.setRecording(message.participantId, message.mode, message.recordingId);
+
+        break;
+      }
+      case 'RecordingStopped': {
+        console.log(`Recording stopped: ${message.recordingId} (${message.path}, ${message.durationMs}ms, ${message.sizeBytes} bytes)`);
+        mgr.setRecording(message.participantId, message.mode, undefined);
+
+        break;
+      }
+      case 'RecordingFailed': {
+        console.error(`Recording ${message.recordingId} failed: ${message.reason}`);
+        mgr.setRecording(message.participantId, message.mode, undefined);
+
+        break;
+      }
+      case 'RoomClosed': {
+        console.error(`Room was closed: ${message.reason}`);
+
+        break;
+      }
+      case 'Error': {
+        console.error(`${message.requestAction ?? 'Request'} failed (${message.code}): ${message.message}`);
+
+        break;
+      }
       default: {
         console.error('Received unexpected message', message);
       }
     }
   };
 
-  ws.onmessage = (message) => {
-    const decodedMessage: ServerMessage = JSON.parse(message.data);
+  const connect = () => {
+    const url = new URL(wsUrl);
+    if (sessionToken) {
+      url.searchParams.set('sessionToken', sessionToken);
+    }
+    ws = new WebSocket(url.toString());
 
-    // All other messages go here and are assumed to be notifications
-    // that correspond to previously sent requests
-    const callback = waitingForResponse.get(decodedMessage.action);
+    ws.onmessage = (message) => {
+      const decodedMessage: ServerMessage = JSON.parse(message.data);
 
-    if (callback) {
-      waitingForResponse.delete(decodedMessage.action);
-      callback(decodedMessage);
-    }
-    else {
-      // Simple hack to make sure we process all messages in order, in real-world apps
-      // messages it would be useful to have messages being processed concurrently
-      sequentialMessages = sequentialMessages
-        .then(() => {
-          return onmessage(decodedMessage);
-        })
-        .catch((error) => {
-          console.error('Unexpected error during message handling:', error);
-        });
-    }
+      // Responses carry the ID of the request they correspond to
+      const requestId = 'requestId' in decodedMessage ? decodedMessage.requestId : undefined;
+      const callbacks = requestId !== undefined ? waitingForResponse.get(requestId) : undefined;
+
+      if (requestId !== undefined && callbacks) {
+        waitingForResponse.delete(requestId);
+        if (decodedMessage.action === 'Error') {
+          callbacks.reject(new Error(`${decodedMessage.code}: ${decodedMessage.message}`));
+        }
+        else {
+          callbacks.resolve(decodedMessage);
+        }
+      }
+      else {
+        // Simple hack to make sure we process all messages in order, in real-world apps
+        // messages it would be useful to have messages being processed concurrently
+        sequentialMessages = sequentialMessages
+          .then(() => {
+            return onmessage(decodedMessage);
+          })
+          .catch((error) => {
+            console.error('Unexpected error during message handling:', error);
+          });
+      }
+    };
+    ws.onerror = console.error;
+    ws.onclose = (event) => {
+      // Requests sent over the lost connection will never be answered
+      for (const { reject } of waitingForResponse.values()) {
+        reject(new Error('WebSocket connection closed'));
+      }
+      waitingForResponse.clear();
+
+      // Server keeps the participant in the room for a while, so reconnect to the same session
+      if (sessionToken && !event.wasClean) {
+        console.warn(`WebSocket connection lost (${event.code}), reconnecting`);
+        setTimeout(connect, RECONNECT_DELAY_MS);
+      }
+    };
   };
-  ws.onerror = console.error;
+
+  connect();
 }